@@ -0,0 +1,309 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serenity::prelude::TypeMapKey;
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, MutexGuard};
+
+use super::{ffmpeg::LoudnormConfig, types::AudioSource};
+
+const INDEX_FILE: &str = "index.json";
+
+// files are written under a temporary name first, so a crash can't leave a partial file behind
+// under its real name
+static NEXT_TEMP_FILE: AtomicU64 = AtomicU64::new(0);
+
+// what is known about a track regardless of how it was normalized
+#[derive(Clone, Serialize, Deserialize)]
+pub(super) struct CachedTrack {
+    pub duration: Option<Duration>,
+    // measured by the first loudnorm pass, which doesn't depend on the loudnorm targets
+    pub loudnorm: Option<LoudnormConfig>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedFile {
+    identity: String,
+    size: u64,
+    // the value of the index's clock when the file was last read or written
+    last_used: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheIndex {
+    // the track each yt-dlp query resolved to, so repeats don't run yt-dlp again
+    queries: HashMap<String, String>,
+    tracks: HashMap<String, CachedTrack>,
+    // keyed by file name
+    files: HashMap<String, CachedFile>,
+    clock: u64,
+}
+
+impl CacheIndex {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn size(&self) -> u64 {
+        self.files.values().map(|file| file.size).sum()
+    }
+
+    // tracks are forgotten along with their last file
+    fn prune(&mut self) {
+        let files = &self.files;
+        self.tracks
+            .retain(|identity, _| files.values().any(|file| &file.identity == identity));
+        let tracks = &self.tracks;
+        self.queries
+            .retain(|_, identity| tracks.contains_key(identity));
+    }
+}
+
+// normalized audio on disk, keyed by the track it was made from and how it was normalized.
+// The least recently used files are deleted once the cache grows past max_size bytes
+pub struct AudioCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<CacheIndex>,
+    // the clock of the last index that was written, so an older one can't overwrite it
+    saved_clock: Mutex<u64>,
+}
+
+// the same track and normalization always map to the same file
+fn file_name(identity: &str, variant: &str) -> String {
+    let hash = Sha256::digest(format!("{identity}\n{variant}"));
+    let hex: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("{hex}.mp3")
+}
+
+async fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let temp_path = path.with_extension(format!(
+        "{}.tmp",
+        NEXT_TEMP_FILE.fetch_add(1, Ordering::Relaxed)
+    ));
+    let res = match tokio::fs::write(&temp_path, data).await {
+        Ok(()) => tokio::fs::rename(&temp_path, path).await,
+        Err(why) => Err(why),
+    };
+    if res.is_err() {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    res
+}
+
+// edited files are treated as different tracks
+async fn local_file_identity(path: &Path) -> Option<String> {
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some(format!(
+        "file:{}:{}:{}",
+        path.display(),
+        metadata.len(),
+        modified
+    ))
+}
+
+impl AudioCache {
+    pub fn open(dir: PathBuf, max_size: u64) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir).context("failed to create audio cache directory")?;
+        let index_path = dir.join(INDEX_FILE);
+        let mut index: CacheIndex = match fs::exists(&index_path)
+            .context("failed to check existence of file")?
+        {
+            true => {
+                let data = fs::read_to_string(&index_path).context("failed to load audio cache")?;
+                serde_json::from_str(&data).context("failed to deserialize audio cache index")?
+            }
+            false => CacheIndex::default(),
+        };
+        // files may have been deleted while the bot was not running
+        index.files.retain(|name, _| dir.join(name).exists());
+        index.prune();
+        // and files the index doesn't know about, e.g. from a crash while writing, would never
+        // be evicted
+        for entry in fs::read_dir(&dir).context("failed to read audio cache directory")? {
+            let entry = entry.context("failed to read audio cache directory")?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name == INDEX_FILE || index.files.contains_key(&name) {
+                continue;
+            }
+            log::info!("deleting unknown file {} from the audio cache", name);
+            if let Err(why) = fs::remove_file(entry.path()) {
+                log::warn!("failed to delete {}: {}", name, why);
+            }
+        }
+        log::info!(
+            "audio cache has {} files, {} bytes",
+            index.files.len(),
+            index.size()
+        );
+        Ok(Self {
+            dir,
+            max_size,
+            index: Mutex::new(index),
+            saved_clock: Mutex::new(0),
+        })
+    }
+
+    // the index is serialized while locked, then written once it is unlocked
+    async fn save_index(&self, mut index: MutexGuard<'_, CacheIndex>) {
+        let clock = index.tick();
+        let data = match serde_json::to_vec(&*index) {
+            Ok(data) => data,
+            Err(why) => {
+                log::warn!("failed to serialize audio cache index: {}", why);
+                return;
+            }
+        };
+        drop(index);
+        let mut saved_clock = self.saved_clock.lock().await;
+        if *saved_clock > clock {
+            return;
+        }
+        match write_atomic(&self.dir.join(INDEX_FILE), &data).await {
+            Ok(()) => *saved_clock = clock,
+            Err(why) => log::warn!("failed to save audio cache index: {}", why),
+        }
+    }
+
+    pub(super) fn ytdl_identity(track_id: &str) -> String {
+        format!("ytdl:{track_id}")
+    }
+
+    // the track the source refers to, if it can be known without resolving it
+    pub(super) async fn identity(&self, source: &AudioSource) -> Option<String> {
+        match source {
+            AudioSource::Ytdl(query) => self.index.lock().await.queries.get(query).cloned(),
+            AudioSource::Url(url) => Some(format!("url:{url}")),
+            AudioSource::LocalFile(path) => local_file_identity(path).await,
+        }
+    }
+
+    pub(super) async fn get_track(&self, identity: &str) -> Option<CachedTrack> {
+        self.index.lock().await.tracks.get(identity).cloned()
+    }
+
+    pub(super) async fn get_audio(&self, identity: &str, variant: &str) -> Option<Vec<u8>> {
+        let name = file_name(identity, variant);
+        if !self.index.lock().await.files.contains_key(&name) {
+            return None;
+        }
+        // the index isn't locked while reading, so the file may have been evicted since
+        let buf = tokio::fs::read(self.dir.join(&name)).await;
+        let mut index = self.index.lock().await;
+        match buf {
+            Ok(buf) => {
+                let clock = index.tick();
+                // saved along with the next change to the files, a hit alone isn't worth a write
+                if let Some(file) = index.files.get_mut(&name) {
+                    file.last_used = clock;
+                }
+                log::info!("loaded {} from the audio cache", identity);
+                Some(buf)
+            }
+            Err(why) => {
+                log::warn!("failed to read cached audio for {}: {}", identity, why);
+                index.files.remove(&name);
+                index.prune();
+                self.save_index(index).await;
+                None
+            }
+        }
+    }
+
+    pub(super) async fn insert(
+        &self,
+        identity: &str,
+        track: CachedTrack,
+        variant: &str,
+        buf: &[u8],
+    ) {
+        let size = buf.len() as u64;
+        if size > self.max_size {
+            return;
+        }
+        let name = file_name(identity, variant);
+        if let Err(why) = write_atomic(&self.dir.join(&name), buf).await {
+            log::warn!("failed to write cached audio for {}: {}", identity, why);
+            return;
+        }
+        let mut index = self.index.lock().await;
+        let last_used = index.tick();
+        index.files.insert(
+            name,
+            CachedFile {
+                identity: identity.to_string(),
+                size,
+                last_used,
+            },
+        );
+        // keep what was already known, in case this normalization didn't need it
+        let cached = index
+            .tracks
+            .entry(identity.to_string())
+            .or_insert(CachedTrack {
+                duration: None,
+                loudnorm: None,
+            });
+        cached.duration = track.duration.or(cached.duration);
+        cached.loudnorm = track.loudnorm.or(cached.loudnorm.take());
+
+        let mut total = index.size();
+        let mut evicted = vec![];
+        while total > self.max_size {
+            let oldest = index
+                .files
+                .iter()
+                .min_by_key(|(_, file)| file.last_used)
+                .map(|(name, _)| name.clone());
+            let Some(oldest) = oldest else {
+                break;
+            };
+            if let Some(file) = index.files.remove(&oldest) {
+                total -= file.size;
+            }
+            evicted.push(oldest);
+        }
+        index.prune();
+        self.save_index(index).await;
+        // a file that is being read while it is deleted just fails to load from the cache
+        for name in evicted {
+            if let Err(why) = tokio::fs::remove_file(self.dir.join(&name)).await {
+                log::warn!("failed to delete cached audio {}: {}", name, why);
+            }
+        }
+    }
+
+    // only remembered while the track is cached
+    pub(super) async fn remember_query(&self, query: &str, identity: &str) {
+        let mut index = self.index.lock().await;
+        if index.tracks.contains_key(identity)
+            && index.queries.get(query).map(String::as_str) != Some(identity)
+        {
+            index
+                .queries
+                .insert(query.to_string(), identity.to_string());
+            self.save_index(index).await;
+        }
+    }
+}
+
+impl TypeMapKey for AudioCache {
+    type Value = Arc<Self>;
+}
@@ -1,25 +1,41 @@
-use crate::{util::send_embed, PoiseContext};
-use anyhow::anyhow;
+use crate::{
+    util::{format_duration, progress_bar, send_embed},
+    PoiseContext,
+};
+use anyhow::{anyhow, Context as AContext};
 use async_trait::async_trait;
 use rand::seq::SliceRandom;
 use std::{
+    collections::{HashSet, VecDeque},
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
-use super::{config, types::QueuePosition};
 use super::{
+    audio_cache::AudioCache,
     ffmpeg::get_audio_reader,
+    filters::FilterChain,
+    icy::fetch_stream_title,
     message_ui_component::MessageUiComponent,
-    song::Song,
+    now_playing_component::NowPlayingComponent,
+    queue_policy::{get_rejections_string, QueuePolicy},
+    session::{SavedSession, SessionDb},
+    settings::{GuildSettings, SettingsDb},
+    song::{Song, SongRecord},
     song_queue::SongQueue,
-    song_searcher::{process_query, song_recommender},
+    song_searcher::{process_attachment, process_query, song_recommender},
     types::StreamType,
 };
-use poise::serenity_prelude::{ChannelId, Context};
+use super::{
+    config,
+    types::{
+        AudioReaderConfig, LoadFailure, LoopMode, LoudnormTargets, QueuePosition, SkipOutcome,
+    },
+};
+use poise::serenity_prelude::{Attachment, ChannelId, Context, GuildId, UserId};
 use songbird::{
     error::TrackResult,
     input::{
@@ -31,17 +47,48 @@ use songbird::{
     Call, Event, EventContext, EventHandler as VoiceEventHandler, TrackEvent,
 };
 use tokio::{
-    sync::Mutex,
-    time::{sleep_until, Instant},
+    sync::{
+        mpsc::{self, UnboundedReceiver},
+        Mutex, Notify,
+    },
+    task::JoinHandle,
+    time::sleep,
 };
 
 pub struct AudioState {
+    guild_id: GuildId,
+    settings: Mutex<GuildSettings>,
     queue: SongQueue,
+    player_wakeup: Arc<Notify>,
+    play_loop_handle: Mutex<Option<JoinHandle<()>>>,
+    failure_reporter_handle: Mutex<Option<JoinHandle<()>>>,
     handler: Arc<Mutex<Call>>,
     current_song: Mutex<Option<Song>>,
     next_looping_song_to_play: Mutex<Option<Song>>,
     track_handle: Mutex<Option<TrackHandle>>,
-    is_looping: Mutex<bool>,
+    // incremented every time a track is started, see SongEndNotifier
+    track_generation: AtomicU64,
+    // the generation of the track that was last skipped, which isn't looped
+    skipped_generation: AtomicU64,
+    // position in the song at which the current track was started
+    track_start_offset: Mutex<Duration>,
+    // how much faster than the song the current track plays, because of filters
+    track_speed: Mutex<f64>,
+    crossfade_watcher: Mutex<Option<JoinHandle<()>>>,
+    crossfade_requested: AtomicBool,
+    // the title currently played by a radio stream, polled by the stream title watcher
+    stream_title: Mutex<Option<String>>,
+    stream_title_watcher: Mutex<Option<JoinHandle<()>>>,
+    // users who voted to skip the current song
+    skip_votes: Mutex<HashSet<UserId>>,
+    // whether playback was paused because everyone left the voice channel
+    auto_paused: AtomicBool,
+    idle_disconnect_handle: Mutex<Option<JoinHandle<()>>>,
+    loop_mode: Mutex<LoopMode>,
+    // most recently played song first
+    history: Mutex<VecDeque<SongRecord>>,
+    // songs that failed to load and were skipped, most recent first
+    failed_songs: Mutex<VecDeque<SongRecord>>,
     // song_ready: Semaphore,
     current_stream_type: Mutex<StreamType>,
     is_paused: AtomicBool,
@@ -50,32 +97,77 @@ pub struct AudioState {
     context: Mutex<Arc<Context>>,
 
     message_ui_component: Mutex<Option<MessageUiComponent>>,
+    now_playing_component: Mutex<Option<NowPlayingComponent>>,
 }
 
 impl AudioState {
-    pub fn new(handler: Arc<Mutex<Call>>, ctx: &PoiseContext<'_>) -> Arc<AudioState> {
+    pub fn new(
+        handler: Arc<Mutex<Call>>,
+        guild_id: GuildId,
+        settings: GuildSettings,
+        cache: Arc<AudioCache>,
+        context: Arc<Context>,
+        channel_id: ChannelId,
+    ) -> Arc<AudioState> {
+        let player_wakeup = Arc::new(Notify::new());
+        let (failures_tx, failures_rx) = mpsc::unbounded_channel();
         let audio_state = AudioState {
-            queue: SongQueue::new(),
+            guild_id,
+            queue: SongQueue::new(
+                player_wakeup.clone(),
+                settings.fair_queue,
+                settings.queue_policy.clone(),
+                settings.loudnorm_targets,
+                cache,
+                failures_tx,
+            ),
+            settings: Mutex::new(settings),
+            player_wakeup,
+            play_loop_handle: Mutex::new(None),
+            failure_reporter_handle: Mutex::new(None),
             handler,
             current_song: Mutex::new(None),
             next_looping_song_to_play: Mutex::new(None),
             track_handle: Mutex::new(None),
-            is_looping: Mutex::new(false),
+            track_generation: AtomicU64::new(0),
+            skipped_generation: AtomicU64::new(0),
+            track_start_offset: Mutex::new(Duration::ZERO),
+            track_speed: Mutex::new(1.0),
+            crossfade_watcher: Mutex::new(None),
+            crossfade_requested: AtomicBool::new(false),
+            stream_title: Mutex::new(None),
+            stream_title_watcher: Mutex::new(None),
+            skip_votes: Mutex::new(HashSet::new()),
+            auto_paused: AtomicBool::new(false),
+            idle_disconnect_handle: Mutex::new(None),
+            loop_mode: Mutex::new(LoopMode::default()),
+            history: Mutex::new(VecDeque::new()),
+            failed_songs: Mutex::new(VecDeque::new()),
             // song_ready: Semaphore::new(1),
             current_stream_type: Mutex::new(StreamType::Loudnorm),
             is_paused: AtomicBool::new(false),
 
-            channel_id: Mutex::new(ctx.channel_id()),
-            context: Mutex::new(Arc::new(ctx.serenity_context().clone())),
+            channel_id: Mutex::new(channel_id),
+            context: Mutex::new(context),
 
             message_ui_component: Mutex::new(None),
+            now_playing_component: Mutex::new(None),
         };
         let audio_state = Arc::new(audio_state);
         {
-            let audio_state = audio_state.clone();
-            tokio::spawn(async move {
-                audio_state.play_audio_loop().await;
+            let audio_state_clone = audio_state.clone();
+            let job_handle = tokio::spawn(async move {
+                audio_state_clone.play_audio_loop().await;
+            });
+            // the state was only just created, so nothing else can be holding this lock
+            *audio_state.play_loop_handle.try_lock().unwrap() = Some(job_handle);
+        }
+        {
+            let audio_state_clone = audio_state.clone();
+            let job_handle = tokio::spawn(async move {
+                audio_state_clone.report_load_failures(failures_rx).await;
             });
+            *audio_state.failure_reporter_handle.try_lock().unwrap() = Some(job_handle);
         }
         audio_state
     }
@@ -93,15 +185,17 @@ impl AudioState {
 
     async fn play_audio_loop(self: &Arc<Self>) {
         loop {
-            sleep_until(
-                Instant::now()
-                    .checked_add(config::audio::AUDIO_LOOP_POLL_INTERVAL)
-                    .unwrap(),
-            )
-            .await;
+            // woken up when a song ends, or when the next song in the queue may be ready
+            self.player_wakeup.notified().await;
 
             {
                 if self.current_song.lock().await.is_some() {
+                    if self.crossfade_requested.swap(false, Ordering::SeqCst) {
+                        if let Err(why) = self.crossfade().await {
+                            log::error!("Error in AudioState::play_audio: {}", why);
+                            self.player_wakeup.notify_one();
+                        }
+                    }
                     continue;
                 }
             }
@@ -109,66 +203,349 @@ impl AudioState {
             let next_looping_song_to_play = { self.next_looping_song_to_play.lock().await.take() };
             let next_song = match next_looping_song_to_play {
                 Some(song) => Some(song),
-                None => self.queue.try_pop_ready_song().await,
+                None => self.pop_playable_song().await,
             };
-            let audio_reader_config = next_song.as_ref().and_then(Song::get_buf_config);
-            if let (Some(song), Some(buf_config)) = (next_song, audio_reader_config) {
-                let source = get_audio_reader(buf_config).await;
-                let source = match source {
-                    Ok(source) => source,
-                    Err(why) => {
-                        log::error!("Error in AudioState::play_audio: {}", why);
-                        // self.play_next_song();
-                        continue;
-                    }
+            if let Some(song) = next_song {
+                if let Err(why) = self.play_song(song, Duration::ZERO).await {
+                    log::error!("Error in AudioState::play_audio: {}", why);
+                    // try the next song straight away
+                    self.player_wakeup.notify_one();
+                }
+            }
+        }
+    }
+
+    // announces songs as soon as they fail to load, rather than when they would have played
+    async fn report_load_failures(
+        &self,
+        mut failures: UnboundedReceiver<(SongRecord, LoadFailure)>,
+    ) {
+        while let Some((record, reason)) = failures.recv().await {
+            // retrying won't help songs rejected by the queue policy, so they were dropped
+            let text = match reason.is_retryable() {
+                true => format!("Failed to load {}: {}", record.get_string(), reason),
+                false => format!("Dropped {}: {}", record.get_string(), reason),
+            };
+            if let Err(why) = self.announce(&text).await {
+                log::error!("Err AudioState::report_load_failures: {:?}", why);
+            }
+        }
+    }
+
+    // pops the next song if it is ready, skipping songs that failed to load. They were already
+    // announced, and are kept for retrying
+    async fn pop_playable_song(&self) -> Option<Song> {
+        loop {
+            let song = self.queue.try_pop_ready_song().await?;
+            if song.load_failure().is_none() {
+                return Some(song);
+            }
+            let mut failed_songs = self.failed_songs.lock().await;
+            failed_songs.push_front(song.record());
+            failed_songs.truncate(config::audio::FAILED_SONGS_LENGTH);
+        }
+    }
+
+    // starts playing a ready song, fading it in over the given duration
+    async fn play_song(self: &Arc<Self>, song: Song, fade_in: Duration) -> anyhow::Result<()> {
+        let buf_config = song.get_buf_config().context("song is not ready")?;
+        let handle = self.start_track(buf_config, Duration::ZERO).await?;
+        if !fade_in.is_zero() {
+            handle.set_volume(0.0)?;
+            let handle = handle.clone();
+            let volume = self.get_volume().await;
+            tokio::spawn(async move { fade(&handle, 0.0, volume, fade_in).await });
+        }
+        let duration = song.duration();
+        let live_url = song.live_url().map(str::to_string);
+        {
+            let mut current_song = self.current_song.lock().await;
+            *current_song = Some(song);
+            let mut track_handle = self.track_handle.lock().await;
+            *track_handle = Some(handle);
+            *self.track_start_offset.lock().await = Duration::ZERO;
+            self.is_paused.store(false, Ordering::Relaxed);
+            self.crossfade_requested.store(false, Ordering::SeqCst);
+            self.skip_votes.lock().await.clear();
+        }
+        self.start_crossfade_watcher(duration).await;
+        self.start_stream_title_watcher(live_url).await;
+        {
+            let channel_id = self.channel_id.lock().await;
+
+            let context = self.context.lock().await;
+
+            let mut component = NowPlayingComponent::new(self.clone(), context.clone());
+            if let Err(why) = component.start_with_channel_id(*channel_id).await {
+                log::error!("Err AudioState::play_audio: {:?}", why);
+            }
+            *self.now_playing_component.lock().await = Some(component);
+        }
+
+        if let Err(why) = self.display_ui().await {
+            log::error!("Err AudioState::play_audio: {:?}", why);
+        }
+        Ok(())
+    }
+
+    // requests a crossfade once the current song is about to end, if crossfading is enabled
+    async fn start_crossfade_watcher(self: &Arc<Self>, duration: Option<Duration>) {
+        let mut crossfade_watcher = self.crossfade_watcher.lock().await;
+        if let Some(job_handle) = crossfade_watcher.take() {
+            job_handle.abort();
+        }
+        let crossfade = Duration::from_secs(self.settings.lock().await.crossfade_secs);
+        let duration = match duration {
+            Some(duration) if !crossfade.is_zero() => duration,
+            _ => return,
+        };
+        let audio_state = self.clone();
+        *crossfade_watcher = Some(tokio::spawn(async move {
+            loop {
+                let position = match audio_state.get_position().await {
+                    Ok(position) => position,
+                    Err(_) => return,
                 };
-                let input = input::Input::Live(
-                    LiveInput::Wrapped(AudioStream {
-                        input: MediaSourceStream::new(source, MediaSourceStreamOptions::default()),
-                        hint: None,
-                    }),
-                    None,
-                );
-
-                let mut handler = self.handler.lock().await;
-
-                let handle = handler.play_input(input);
-
-                if let Err(why) = handle.add_event(
-                    Event::Track(TrackEvent::End),
-                    SongEndNotifier {
-                        audio_state: self.clone(),
-                    },
-                ) {
-                    log::error!("Err AudioState::play_audio: {:?}", why);
+                let remaining = duration.saturating_sub(position).saturating_sub(crossfade);
+                if remaining.is_zero() {
+                    audio_state
+                        .crossfade_requested
+                        .store(true, Ordering::SeqCst);
+                    audio_state.player_wakeup.notify_one();
+                    return;
                 }
-                {
-                    let text = song.get_string().await;
-                    let channel_id = self.channel_id.lock().await;
+                // the position may jump because of seeks, or stop because of pauses
+                sleep(remaining.min(config::audio::CROSSFADE_CHECK_INTERVAL)).await;
+            }
+        }));
+    }
+
+    // keeps the stream title up to date while a live stream is playing
+    async fn start_stream_title_watcher(self: &Arc<Self>, live_url: Option<String>) {
+        let mut stream_title_watcher = self.stream_title_watcher.lock().await;
+        if let Some(job_handle) = stream_title_watcher.take() {
+            job_handle.abort();
+        }
+        *self.stream_title.lock().await = None;
+        let live_url = match live_url {
+            Some(live_url) => live_url,
+            None => return,
+        };
+        let audio_state = self.clone();
+        *stream_title_watcher = Some(tokio::spawn(async move {
+            loop {
+                match fetch_stream_title(&live_url).await {
+                    Ok(title) => *audio_state.stream_title.lock().await = title,
+                    Err(why) => log::warn!("failed to fetch stream title: {}", why),
+                }
+                sleep(config::audio::STREAM_TITLE_POLL_INTERVAL).await;
+            }
+        }));
+    }
 
-                    let context = self.context.lock().await;
+    // starts the next song while the current one fades out
+    async fn crossfade(self: &Arc<Self>) -> anyhow::Result<()> {
+        // a looping track restarts when it ends instead
+        if self.get_loop_mode().await == LoopMode::Track {
+            return Ok(());
+        }
+        let next_song = match self.pop_playable_song().await {
+            Some(song) => song,
+            None => return Ok(()),
+        };
+        let crossfade = Duration::from_secs(self.settings.lock().await.crossfade_secs);
+        *self.now_playing_component.lock().await = None;
+        let finished_song = self.current_song.lock().await.take();
+        if let Some(song) = finished_song {
+            self.on_song_finished(song, false).await;
+        }
+        if let Some(old_handle) = self.track_handle.lock().await.take() {
+            let volume = self.get_volume().await;
+            tokio::spawn(async move {
+                fade(&old_handle, volume, 0.0, crossfade).await;
+                let _ = old_handle.stop();
+            });
+        }
+        // starting the next track makes the old one stale, so it won't trigger SongEndNotifier
+        self.play_song(next_song, crossfade).await
+    }
 
-                    if let Err(why) = send_embed(
-                        &context.http,
-                        *channel_id,
-                        &format!("Now playing:\n\n {}", text),
-                    )
+    // keeps track of a song that is no longer playing, according to the loop mode. A looping
+    // track that was skipped moves on to the next song
+    async fn on_song_finished(&self, song: Song, skipped: bool) {
+        let loop_mode = { *self.loop_mode.lock().await };
+        match loop_mode {
+            // this is sound because we are guaranteed that the current song is in the Ready state
+            LoopMode::Track if !skipped => {
+                *self.next_looping_song_to_play.lock().await = Some(song)
+            }
+            LoopMode::Queue => {
+                self.push_history(song.record()).await;
+                if let Err(why) = self
+                    .queue
+                    .push(vec![song.into_requeued()], QueuePosition::Back)
                     .await
-                    {
-                        log::error!("Err AudioState::play_audio: {:?}", why);
-                    }
+                {
+                    log::error!("Err AudioState::on_song_finished: {:?}", why);
                 }
+            }
+            LoopMode::Track | LoopMode::Off => self.push_history(song.record()).await,
+        }
+    }
 
-                if let Err(why) = self.display_ui().await {
-                    log::error!("Err AudioState::play_audio: {:?}", why);
-                }
+    async fn get_volume(&self) -> f32 {
+        self.settings.lock().await.volume as f32 / 100.0
+    }
 
-                let mut current_song = self.current_song.lock().await;
-                *current_song = Some(song);
-                let mut track_handle = self.track_handle.lock().await;
-                *track_handle = Some(handle);
+    // plays the audio on the voice call. start is only applied to online streams, since loudnorm
+    // buffers are seekable and can be seeked through the returned track handle instead
+    async fn start_track(
+        self: &Arc<Self>,
+        config: AudioReaderConfig,
+        start: Duration,
+    ) -> anyhow::Result<TrackHandle> {
+        let filters = self.settings.lock().await.filters.clone();
+        let source = get_audio_reader(config, start, filters.to_ffmpeg()).await?;
+        *self.track_speed.lock().await = filters.speed_factor();
+        let input = input::Input::Live(
+            LiveInput::Wrapped(AudioStream {
+                input: MediaSourceStream::new(source, MediaSourceStreamOptions::default()),
+                hint: None,
+            }),
+            None,
+        );
+
+        // any track started before this one is now stale, and must not trigger SongEndNotifier
+        let generation = self.track_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut handler = self.handler.lock().await;
+
+        let handle = handler.play_input(input);
+        // attached first, so that the player moves on when the track ends even if it can't be
+        // configured
+        if let Err(why) = handle.add_event(
+            Event::Track(TrackEvent::End),
+            SongEndNotifier {
+                audio_state: self.clone(),
+                generation,
+            },
+        ) {
+            log::error!("Err AudioState::start_track: {:?}", why);
+        }
+        if let Err(why) = handle.set_volume(self.get_volume().await) {
+            log::error!("Err AudioState::start_track: {:?}", why);
+        }
+        Ok(handle)
+    }
+
+    pub async fn get_settings(&self) -> GuildSettings {
+        self.settings.lock().await.clone()
+    }
+
+    // applies the change to this guild's settings, and persists them
+    async fn update_settings<F: FnOnce(&mut GuildSettings)>(&self, f: F) -> anyhow::Result<()> {
+        let context = self.context.lock().await;
+        let mut data = context.data.write().await;
+        // the stored settings are the source of truth, since some of them (e.g. the permission
+        // policy) are changed without going through the audio state
+        let settings = data
+            .get_mut::<SettingsDb>()
+            .context("SettingsDb object was not initialized in serenity TypeMap")?
+            .update_and_flush(self.guild_id, f)?;
+        *self.settings.lock().await = settings;
+        Ok(())
+    }
+
+    pub async fn set_volume(&self, volume: u32) -> anyhow::Result<()> {
+        if volume > config::audio::MAX_VOLUME {
+            return Err(anyhow!(
+                "volume must be between 0 and {}",
+                config::audio::MAX_VOLUME
+            ));
+        }
+        self.update_settings(|settings| settings.volume = volume)
+            .await?;
+        // the volume also applies to every track started from now on
+        if let Some(track_handle) = self.track_handle.lock().await.as_ref() {
+            track_handle.set_volume(volume as f32 / 100.0)?;
+        }
+        Ok(())
+    }
+
+    // on success, returns the new volume
+    pub async fn change_volume_by(&self, delta: i64) -> anyhow::Result<u32> {
+        let volume = self.settings.lock().await.volume as i64 + delta;
+        let volume = volume.clamp(0, config::audio::MAX_VOLUME as i64) as u32;
+        self.set_volume(volume).await?;
+        Ok(volume)
+    }
+
+    pub async fn get_position(&self) -> anyhow::Result<Duration> {
+        let track_handle = self.track_handle.lock().await;
+        let track_handle = track_handle.as_ref().context("no song currently playing")?;
+        let info = track_handle.get_info().await?;
+        let speed = *self.track_speed.lock().await;
+        Ok(*self.track_start_offset.lock().await + info.position.mul_f64(speed))
+    }
+
+    pub async fn seek(self: &Arc<Self>, position: Duration) -> anyhow::Result<()> {
+        let config = {
+            let current_song = self.current_song.lock().await;
+            let song = current_song.as_ref().context("no song currently playing")?;
+            if song.live_url().is_some() {
+                return Err(anyhow!("live streams can't be seeked"));
             }
+            song.get_buf_config().context("no song currently playing")?
+        };
+        let filters = self.settings.lock().await.filters.clone();
+        match config {
+            config if is_seekable(&config, &filters) => {
+                let track_handle = self.track_handle.lock().await;
+                let track_handle = track_handle.as_ref().context("no song currently playing")?;
+                track_handle.seek_async(position).await?;
+            }
+            // online streams and filtered audio can't be seeked, so we restart ffmpeg at the new
+            // position instead
+            config => self.restart_track(config, position).await?,
         }
+        Ok(())
+    }
+
+    // replaces the current track with a new one playing from the given position
+    async fn restart_track(
+        self: &Arc<Self>,
+        config: AudioReaderConfig,
+        position: Duration,
+    ) -> anyhow::Result<()> {
+        let filters = self.settings.lock().await.filters.clone();
+        let seekable = is_seekable(&config, &filters);
+        let handle = self.start_track(config, position).await?;
+        // the reader ignores the start of seekable audio, which is seeked to instead
+        let offset = match seekable {
+            true => {
+                handle.seek_async(position).await?;
+                Duration::ZERO
+            }
+            false => position,
+        };
+        if self.is_paused.load(Ordering::Relaxed) {
+            handle.pause()?;
+        }
+        let mut track_handle = self.track_handle.lock().await;
+        if let Some(old_handle) = track_handle.replace(handle) {
+            old_handle.stop()?;
+        }
+        *self.track_start_offset.lock().await = offset;
+        Ok(())
+    }
+
+    // seeks relative to the current position, clamping to the start of the song
+    pub async fn seek_by(self: &Arc<Self>, delta_secs: i64) -> anyhow::Result<()> {
+        let position = self.get_position().await?;
+        let position = match delta_secs < 0 {
+            true => position.saturating_sub(Duration::from_secs(delta_secs.unsigned_abs())),
+            false => position + Duration::from_secs(delta_secs as u64),
+        };
+        self.seek(position).await
     }
 
     pub async fn display_ui(self: &Arc<Self>) -> anyhow::Result<()> {
@@ -202,8 +579,9 @@ impl AudioState {
         queue_position: QueuePosition,
         shuffle: bool,
         stream_type: StreamType,
+        requester: UserId,
     ) -> anyhow::Result<()> {
-        let mut songs = process_query(query, stream_type).await?;
+        let mut songs = process_query(query, stream_type, requester).await?;
         if shuffle {
             songs.shuffle(&mut rand::thread_rng());
         }
@@ -212,28 +590,77 @@ impl AudioState {
         // if let (true, Some(work)) = (has_current_song, &mut songs[0].1) {
         //     work.stream_type = StreamType::Online
         // }
-        self.queue.push(songs, queue_position).await?;
-        Ok(())
+        self.push_limited(songs, queue_position).await
     }
 
-    pub async fn add_recommended_songs(&self, query: &str, amount: usize) -> anyhow::Result<()> {
-        let songs = song_recommender(query, amount, *self.current_stream_type.lock().await).await?;
-        self.queue.push(songs, QueuePosition::default()).await?;
-        Ok(())
+    // queues uploaded audio files, in the given order
+    pub async fn add_attachments(
+        &self,
+        attachments: &[Attachment],
+        queue_position: QueuePosition,
+        requester: UserId,
+    ) -> anyhow::Result<()> {
+        let stream_type = self.get_stream_type().await;
+        let mut songs = vec![];
+        for attachment in attachments {
+            songs.push(process_attachment(attachment, stream_type, requester).await?);
+        }
+        self.push_limited(songs, queue_position).await
+    }
+
+    // pushes the songs allowed by the queue policy and announces the ones that were dropped. Fails
+    // if no songs could be added
+    async fn push_limited(
+        &self,
+        songs: Vec<Song>,
+        queue_position: QueuePosition,
+    ) -> anyhow::Result<()> {
+        let num_songs = songs.len();
+        let rejections = self.queue.push_limited(songs, queue_position).await;
+        if rejections.is_empty() {
+            return Ok(());
+        }
+        let text = get_rejections_string(&rejections);
+        if rejections.len() == num_songs {
+            return Err(anyhow!("{}", text));
+        }
+        self.announce(&text).await
+    }
+
+    pub async fn add_recommended_songs(
+        &self,
+        query: &str,
+        amount: usize,
+        requester: UserId,
+    ) -> anyhow::Result<()> {
+        let songs = song_recommender(
+            query,
+            amount,
+            *self.current_stream_type.lock().await,
+            requester,
+        )
+        .await?;
+        self.push_limited(songs, QueuePosition::default()).await
     }
 
-    pub async fn extend_songs(&self, query: &str, extend_ratio: f64) -> anyhow::Result<()> {
-        let mut songs = process_query(query, *self.current_stream_type.lock().await).await?;
+    pub async fn extend_songs(
+        &self,
+        query: &str,
+        extend_ratio: f64,
+        requester: UserId,
+    ) -> anyhow::Result<()> {
+        let mut songs =
+            process_query(query, *self.current_stream_type.lock().await, requester).await?;
         let recommended_songs = song_recommender(
             query,
             (songs.len() as f64 * extend_ratio) as usize,
             *self.current_stream_type.lock().await,
+            requester,
         )
         .await?;
         songs.extend(recommended_songs);
         songs.shuffle(&mut rand::thread_rng());
-        self.queue.push(songs, QueuePosition::default()).await?;
-        Ok(())
+        self.push_limited(songs, QueuePosition::default()).await
     }
 
     pub async fn send_track_command<F: Fn(&TrackHandle) -> TrackResult<()>>(
@@ -249,13 +676,119 @@ impl AudioState {
 
     pub async fn pause_resume(&self, try_pause: Option<bool>) -> anyhow::Result<()> {
         // if None, then we reverse the current play/pause state
-        let try_pause = try_pause.unwrap_or(!self.is_paused.fetch_xor(true, Ordering::Relaxed));
+        let try_pause = try_pause.unwrap_or(!self.is_paused.load(Ordering::Relaxed));
+        self.is_paused.store(try_pause, Ordering::Relaxed);
+        let track_handle = self
+            .track_handle
+            .lock()
+            .await
+            .clone()
+            .context("no song currently playing")?;
+        let volume = self.get_volume().await;
         // not paused previously
         if try_pause {
-            self.send_track_command(TrackHandle::pause).await
+            fade(&track_handle, volume, 0.0, config::audio::FADE_DURATION).await;
+            track_handle.pause()?;
         } else {
-            self.send_track_command(TrackHandle::play).await
+            track_handle.set_volume(0.0)?;
+            track_handle.play()?;
+            fade(&track_handle, 0.0, volume, config::audio::FADE_DURATION).await;
         }
+        Ok(())
+    }
+
+    pub async fn skip(&self) -> anyhow::Result<()> {
+        let track_handle = self
+            .track_handle
+            .lock()
+            .await
+            .clone()
+            .context("no song currently playing")?;
+        self.skipped_generation.store(
+            self.track_generation.load(Ordering::SeqCst),
+            Ordering::SeqCst,
+        );
+        let volume = self.get_volume().await;
+        fade(&track_handle, volume, 0.0, config::audio::FADE_DURATION).await;
+        track_handle.stop()?;
+        Ok(())
+    }
+
+    // whether the user's skip goes ahead: straight away if vote skipping is disabled, the user may
+    // bypass it or requested the current song, otherwise once enough listeners voted for it
+    async fn count_skip_vote(
+        &self,
+        user_id: UserId,
+        can_bypass: bool,
+    ) -> anyhow::Result<SkipOutcome> {
+        let settings = self.get_settings().await;
+        let requester = match self.current_song.lock().await.as_ref() {
+            Some(song) => song.requester(),
+            // there is nothing to vote on
+            None => return Ok(SkipOutcome::Skipped),
+        };
+        if !settings.vote_skip || can_bypass || requester == Some(user_id) {
+            return Ok(SkipOutcome::Skipped);
+        }
+        let listeners = self.get_listeners().await?;
+        if !listeners.contains(&user_id) {
+            return Err(anyhow!(
+                "only listeners in the voice channel can vote to skip"
+            ));
+        }
+        let votes = {
+            let mut skip_votes = self.skip_votes.lock().await;
+            skip_votes.insert(user_id);
+            // votes from users who have since left don't count
+            skip_votes.retain(|user_id| listeners.contains(user_id));
+            skip_votes.len()
+        };
+        let required = required_skip_votes(listeners.len(), settings.vote_skip_ratio);
+        if votes >= required {
+            return Ok(SkipOutcome::Skipped);
+        }
+        if let Some(component) = self.now_playing_component.lock().await.as_ref() {
+            component.request_refresh();
+        }
+        Ok(SkipOutcome::Voted { votes, required })
+    }
+
+    pub async fn vote_skip(
+        &self,
+        user_id: UserId,
+        can_bypass: bool,
+    ) -> anyhow::Result<SkipOutcome> {
+        let outcome = self.count_skip_vote(user_id, can_bypass).await?;
+        if let SkipOutcome::Skipped = outcome {
+            self.skip().await?;
+        }
+        Ok(outcome)
+    }
+
+    pub async fn set_vote_skip(&self, enabled: bool, ratio: Option<f64>) -> anyhow::Result<()> {
+        if let Some(ratio) = ratio {
+            if !(ratio > 0.0 && ratio <= 1.0) {
+                return Err(anyhow!("vote skip ratio must be between 0 and 1"));
+            }
+        }
+        self.update_settings(|settings| {
+            settings.vote_skip = enabled;
+            if let Some(ratio) = ratio {
+                settings.vote_skip_ratio = ratio;
+            }
+        })
+        .await
+    }
+
+    pub async fn set_crossfade(&self, secs: u64) -> anyhow::Result<()> {
+        if secs > config::audio::MAX_CROSSFADE_SECS {
+            return Err(anyhow!(
+                "crossfade must be between 0 and {} seconds",
+                config::audio::MAX_CROSSFADE_SECS
+            ));
+        }
+        self.update_settings(|settings| settings.crossfade_secs = secs)
+            .await
     }
 
     pub async fn shuffle(&self) -> anyhow::Result<()> {
@@ -266,56 +799,484 @@ impl AudioState {
         self.queue.clear().await
     }
 
-    // on success, returns a bool that specifies whether the queue is now being looped
-    pub async fn change_looping(&self, force_looping: Option<bool>) -> anyhow::Result<bool> {
-        let mut is_looping = self.is_looping.lock().await;
-        *is_looping = force_looping.unwrap_or(!*is_looping);
-        Ok(*is_looping)
+    // if requester is given, only songs requested by them may be removed
+    pub async fn remove(
+        &self,
+        position: usize,
+        requester: Option<UserId>,
+    ) -> anyhow::Result<String> {
+        let song = self.queue.remove(position, requester).await?;
+        Ok(song.get_string().await)
+    }
+
+    pub async fn remove_range(
+        &self,
+        start: usize,
+        end: usize,
+        requester: Option<UserId>,
+    ) -> anyhow::Result<usize> {
+        self.queue.remove_range(start, end, requester).await
+    }
+
+    pub async fn remove_ids(
+        &self,
+        ids: &[u64],
+        requester: Option<UserId>,
+    ) -> anyhow::Result<usize> {
+        self.queue.remove_ids(ids, requester).await
+    }
+
+    pub async fn move_to(&self, from: usize, to: usize) -> anyhow::Result<()> {
+        self.queue.move_to(from, to).await
+    }
+
+    pub async fn swap(&self, a: usize, b: usize) -> anyhow::Result<()> {
+        self.queue.swap(a, b).await
+    }
+
+    // skipping ahead skips the current song too, so it needs the same votes as skipping it
+    pub async fn skip_to(
+        &self,
+        position: usize,
+        user_id: UserId,
+        can_bypass: bool,
+    ) -> anyhow::Result<SkipOutcome> {
+        let outcome = self.count_skip_vote(user_id, can_bypass).await?;
+        if let SkipOutcome::Skipped = outcome {
+            self.queue.skip_to(position).await?;
+            // nothing may be playing, in which case the song at the given position simply plays next
+            let _ = self.skip().await;
+        }
+        Ok(outcome)
+    }
+
+    async fn push_history(&self, record: SongRecord) {
+        let mut history = self.history.lock().await;
+        history.push_front(record);
+        history.truncate(config::audio::PLAY_HISTORY_LENGTH);
+    }
+
+    pub async fn get_history(&self, amount: usize) -> Vec<SongRecord> {
+        let history = self.history.lock().await;
+        history.iter().take(amount).cloned().collect()
+    }
+
+    // re-enqueues the most recently played song at the front of the queue, returns its description
+    pub async fn previous(&self, requester: UserId) -> anyhow::Result<String> {
+        let record = self
+            .history
+            .lock()
+            .await
+            .pop_front()
+            .context("no previously played songs")?;
+        let result = self
+            .push_limited(vec![record.to_song(requester)], QueuePosition::Front)
+            .await;
+        // the song stays in the history if it could not be queued
+        if let Err(err) = result {
+            self.push_history(record).await;
+            return Err(err);
+        }
+        Ok(record.get_string())
+    }
+
+    pub async fn requeue(&self, record: &SongRecord, requester: UserId) -> anyhow::Result<()> {
+        self.push_limited(vec![record.to_song(requester)], QueuePosition::default())
+            .await
+    }
+
+    // queues every song that failed to load for loading again, returns the number of such songs
+    pub async fn retry_failed(&self) -> anyhow::Result<usize> {
+        let failed_songs: Vec<SongRecord> = self.failed_songs.lock().await.drain(..).collect();
+        let mut retried = self.queue.retry_failed().await;
+        let mut rejections = vec![];
+        // songs that could not be queued stay in the failed songs, most recent first
+        let mut rejected_songs = VecDeque::new();
+        for record in failed_songs.into_iter().rev() {
+            let song = record.clone().into_song();
+            let mut song_rejections = self
+                .queue
+                .push_limited(vec![song], QueuePosition::Back)
+                .await;
+            if song_rejections.is_empty() {
+                retried += 1;
+            } else {
+                rejections.append(&mut song_rejections);
+                rejected_songs.push_front(record);
+            }
+        }
+        if !rejected_songs.is_empty() {
+            let mut failed_songs = self.failed_songs.lock().await;
+            failed_songs.extend(rejected_songs);
+            failed_songs.truncate(config::audio::FAILED_SONGS_LENGTH);
+        }
+        if !rejections.is_empty() {
+            let text = get_rejections_string(&rejections);
+            if retried == 0 {
+                return Err(anyhow!("{}", text));
+            }
+            self.announce(&text).await?;
+        }
+        Ok(retried)
+    }
+
+    pub async fn get_queue_song_entries(&self, amount: usize) -> Vec<(u64, String)> {
+        self.queue.get_song_entries(amount).await
+    }
+
+    // if None, cycles to the next loop mode. on success, returns the new loop mode
+    pub async fn change_looping(&self, force_mode: Option<LoopMode>) -> anyhow::Result<LoopMode> {
+        let mut loop_mode = self.loop_mode.lock().await;
+        *loop_mode = force_mode.unwrap_or(loop_mode.next());
+        Ok(*loop_mode)
+    }
+
+    pub async fn get_loop_mode(&self) -> LoopMode {
+        *self.loop_mode.lock().await
+    }
+
+    pub async fn get_stream_type(&self) -> StreamType {
+        *self.current_stream_type.lock().await
     }
 
     pub async fn change_stream_type(&self, stream_type: StreamType) {
         *self.current_stream_type.lock().await = stream_type
     }
 
+    // everything needed to resume the session after a restart, if there is anything to resume
+    pub async fn get_saved_session(&self) -> Option<SavedSession> {
+        let voice_channel_id = self.get_voice_channel().await?;
+        let current_song = self
+            .current_song
+            .lock()
+            .await
+            .as_ref()
+            .map(Song::record)
+            .filter(SongRecord::is_restorable);
+        let mut queue = self.queue.get_records().await;
+        queue.retain(SongRecord::is_restorable);
+        if current_song.is_none() && queue.is_empty() {
+            return None;
+        }
+        Some(SavedSession {
+            voice_channel_id,
+            text_channel_id: *self.channel_id.lock().await,
+            current_song,
+            queue,
+            loop_mode: self.get_loop_mode().await,
+            stream_type: *self.current_stream_type.lock().await,
+        })
+    }
+
+    // the song that was playing is played again from the start
+    pub async fn restore_session(&self, session: SavedSession) -> anyhow::Result<()> {
+        *self.loop_mode.lock().await = session.loop_mode;
+        *self.current_stream_type.lock().await = session.stream_type;
+        let songs = session
+            .current_song
+            .into_iter()
+            .chain(session.queue)
+            .map(SongRecord::into_song)
+            .collect();
+        self.queue.push(songs, QueuePosition::Back).await
+    }
+
+    // tears down the session: stops playback and background tasks, and leaves the voice channel
     pub async fn cleanup(&self) -> anyhow::Result<()> {
+        if let Some(job_handle) = self.play_loop_handle.lock().await.take() {
+            job_handle.abort();
+        }
+        if let Some(job_handle) = self.failure_reporter_handle.lock().await.take() {
+            job_handle.abort();
+        }
+        if let Some(job_handle) = self.crossfade_watcher.lock().await.take() {
+            job_handle.abort();
+        }
+        if let Some(job_handle) = self.stream_title_watcher.lock().await.take() {
+            job_handle.abort();
+        }
+        if let Some(job_handle) = self.idle_disconnect_handle.lock().await.take() {
+            job_handle.abort();
+        }
         self.queue.cleanup().await?;
+        *self.now_playing_component.lock().await = None;
+        *self.message_ui_component.lock().await = None;
+        if let Some(track_handle) = self.track_handle.lock().await.take() {
+            let _ = track_handle.stop();
+        }
+        self.handler.lock().await.leave().await?;
+        // the session was ended on purpose, so it shouldn't be offered for restoring
+        let context = self.context.lock().await;
+        let mut data = context.data.write().await;
+        data.get_mut::<SessionDb>()
+            .context("SessionDb object was not initialized in serenity TypeMap")?
+            .remove_and_flush(self.guild_id)?;
+        Ok(())
+    }
+
+    pub async fn get_voice_channel(&self) -> Option<ChannelId> {
+        let channel_id = self.handler.lock().await.current_channel()?;
+        Some(ChannelId::new(channel_id.0.get()))
+    }
+
+    // non-bot users in the bot's voice channel
+    pub async fn get_listeners(&self) -> anyhow::Result<Vec<UserId>> {
+        let channel_id = self
+            .get_voice_channel()
+            .await
+            .context("not connected to a voice channel")?;
+        let context = self.context.lock().await;
+        let guild = context
+            .cache
+            .guild(self.guild_id)
+            .context("failed to get guild")?;
+        let listeners = guild
+            .voice_states
+            .values()
+            .filter(|voice_state| voice_state.channel_id == Some(channel_id))
+            .filter(|voice_state| {
+                let is_bot = match &voice_state.member {
+                    Some(member) => member.user.bot,
+                    None => context
+                        .cache
+                        .user(voice_state.user_id)
+                        .is_some_and(|user| user.bot),
+                };
+                !is_bot
+            })
+            .map(|voice_state| voice_state.user_id)
+            .collect();
+        Ok(listeners)
+    }
+
+    pub async fn announce(&self, text: &str) -> anyhow::Result<()> {
+        let channel_id = self.channel_id.lock().await;
+        let context = self.context.lock().await;
+        send_embed(&context.http, *channel_id, text).await
+    }
+
+    // pauses playback because nobody is listening, remembering to resume once someone rejoins
+    pub async fn auto_pause(&self) -> anyhow::Result<()> {
+        if self.is_paused.load(Ordering::Relaxed) || self.track_handle.lock().await.is_none() {
+            return Ok(());
+        }
+        self.pause_resume(Some(true)).await?;
+        self.auto_paused.store(true, Ordering::Relaxed);
+        self.announce("Paused, since everyone left the voice channel")
+            .await
+    }
+
+    pub async fn auto_resume(&self) -> anyhow::Result<()> {
+        if !self.auto_paused.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.pause_resume(Some(false)).await
+    }
+
+    pub async fn set_idle_timeout(&self, secs: u64) -> anyhow::Result<()> {
+        self.update_settings(|settings| settings.idle_timeout_secs = secs)
+            .await
+    }
+
+    pub async fn set_fair_queue(&self, enabled: bool) -> anyhow::Result<()> {
+        self.update_settings(|settings| settings.fair_queue = enabled)
+            .await?;
+        self.queue.set_fair(enabled).await;
+        Ok(())
+    }
+
+    // changes the filters, and applies them to the current song by restarting it. Returns the new
+    // filters
+    pub async fn update_filters<F: FnOnce(&mut FilterChain)>(
+        self: &Arc<Self>,
+        f: F,
+    ) -> anyhow::Result<FilterChain> {
+        let mut filters = self.get_settings().await.filters;
+        let old_filters = filters.clone();
+        f(&mut filters);
+        // restarting the track is audible, so only do it when something changed
+        if filters == old_filters {
+            return Ok(filters);
+        }
+        self.update_settings(|settings| settings.filters = filters.clone())
+            .await?;
+        let current = self
+            .current_song
+            .lock()
+            .await
+            .as_ref()
+            .and_then(|song| Some((song.get_buf_config()?, song.live_url().is_some())));
+        if let Some((config, is_live)) = current {
+            // live streams are restarted from wherever they are now
+            let position = match is_live {
+                true => Duration::ZERO,
+                false => self.get_position().await?,
+            };
+            self.restart_track(config, position).await?;
+        }
+        Ok(filters)
+    }
+
+    pub async fn set_loudnorm_targets(&self, targets: LoudnormTargets) -> anyhow::Result<()> {
+        self.update_settings(|settings| settings.loudnorm_targets = targets)
+            .await?;
+        self.queue.set_loudnorm_targets(targets).await;
         Ok(())
     }
 
-    pub async fn get_string(&self) -> String {
-        let current_song = self.current_song.lock().await;
-        let current_song = match &*current_song {
+    pub async fn set_queue_policy(&self, policy: QueuePolicy) -> anyhow::Result<()> {
+        self.update_settings(|settings| settings.queue_policy = policy.clone())
+            .await?;
+        self.queue.set_policy(policy).await;
+        Ok(())
+    }
+
+    // the task that disconnects the bot once it has been alone for too long, if any
+    pub async fn set_idle_disconnect_handle(&self, job_handle: Option<JoinHandle<()>>) {
+        let mut idle_disconnect_handle = self.idle_disconnect_handle.lock().await;
+        if let Some(old_handle) = idle_disconnect_handle.take() {
+            old_handle.abort();
+        }
+        *idle_disconnect_handle = job_handle;
+    }
+
+    pub async fn has_idle_disconnect_handle(&self) -> bool {
+        self.idle_disconnect_handle.lock().await.is_some()
+    }
+
+    // lets the idle disconnect task tear down the session without aborting itself
+    pub async fn detach_idle_disconnect_handle(&self) {
+        self.idle_disconnect_handle.lock().await.take();
+    }
+
+    pub async fn get_now_playing_string(&self) -> String {
+        let (song, duration, is_live) = match self.current_song.lock().await.as_ref() {
+            Some(song) => {
+                let mut text = song.get_string().await;
+                if let Some(title) = self.stream_title.lock().await.as_ref() {
+                    text += &format!("\nStreaming: {title}");
+                }
+                if let Some(requester) = song.requester() {
+                    text += &format!("\nRequested by <@{requester}>");
+                }
+                (text, song.duration(), song.live_url().is_some())
+            }
+            None => return "*Not playing*".to_string(),
+        };
+        let elapsed = self.get_position().await.unwrap_or_default();
+        let progress = match duration {
+            Some(duration) => format!(
+                "`{}` {} / {} ({} remaining)",
+                progress_bar(
+                    elapsed,
+                    duration,
+                    config::audio::NOW_PLAYING_PROGRESS_BAR_WIDTH
+                ),
+                format_duration(elapsed),
+                format_duration(duration),
+                format_duration(duration.saturating_sub(elapsed)),
+            ),
+            None if is_live => format!("🔴 Live | {}", format_duration(elapsed)),
+            None => format!("{} / unknown duration", format_duration(elapsed)),
+        };
+        let settings = self.get_settings().await;
+        let mut text = format!(
+            "**Now playing:**\n{}\n\n{}\n\nVolume: {}% | Loop: {}",
+            song,
+            progress,
+            settings.volume,
+            self.get_loop_mode().await
+        );
+        if settings.vote_skip {
+            let listeners = self.get_listeners().await.unwrap_or_default();
+            let votes = self
+                .skip_votes
+                .lock()
+                .await
+                .iter()
+                .filter(|user_id| listeners.contains(user_id))
+                .count();
+            text += &format!(
+                " | Skip votes: {}/{}",
+                votes,
+                required_skip_votes(listeners.len(), settings.vote_skip_ratio)
+            );
+        }
+        text
+    }
+
+    // returns the given page of the queue (0-indexed, clamped to the last page), and the number
+    // of pages
+    pub async fn get_page_string(&self, page: usize) -> (String, usize) {
+        let current_song = match self.current_song.lock().await.as_ref() {
             Some(song) => song.get_string().await,
             None => "*Not playing*\n".to_string(),
         };
-        format!(
+        let (queue, num_pages) = self
+            .queue
+            .get_page_string(page, config::audio::QUEUE_PAGE_SIZE)
+            .await;
+        let text = format!(
             "**Current Song:**\n{}\n\n**Queue:**\n{}",
-            current_song,
-            self.queue.get_string().await
-        )
+            current_song, queue
+        );
+        (text, num_pages)
     }
 }
 
 struct SongEndNotifier {
     audio_state: Arc<AudioState>,
+    generation: u64,
 }
 
 #[async_trait]
 impl VoiceEventHandler for SongEndNotifier {
     async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        // the track was replaced by a restarted one (e.g. when seeking), so the song hasn't ended
+        if self.audio_state.track_generation.load(Ordering::SeqCst) != self.generation {
+            return None;
+        }
         println!("song ended, {:?}", SystemTime::now());
+        *self.audio_state.now_playing_component.lock().await = None;
+        if let Some(job_handle) = self.audio_state.crossfade_watcher.lock().await.take() {
+            job_handle.abort();
+        }
+        if let Some(job_handle) = self.audio_state.stream_title_watcher.lock().await.take() {
+            job_handle.abort();
+        }
         let mut current_song = self.audio_state.current_song.lock().await;
-
-        let is_looping = { *self.audio_state.is_looping.lock().await };
-        if is_looping {
-            // this is sound because we are guaranteed that the current song is in the Ready state
-            *self.audio_state.next_looping_song_to_play.lock().await = current_song.take()
+        if let Some(song) = current_song.take() {
+            let skipped =
+                self.audio_state.skipped_generation.load(Ordering::SeqCst) == self.generation;
+            self.audio_state.on_song_finished(song, skipped).await;
         }
 
-        *current_song = None;
         let mut track_handle = self.audio_state.track_handle.lock().await;
         *track_handle = None;
+        self.audio_state.player_wakeup.notify_one();
 
         None
     }
 }
+
+// bare loudnorm buffers can be seeked by the track handle, everything else is seeked by starting
+// ffmpeg at the new position
+fn is_seekable(config: &AudioReaderConfig, filters: &FilterChain) -> bool {
+    matches!(config, AudioReaderConfig::Loudnorm { .. }) && filters.is_empty()
+}
+
+fn required_skip_votes(listeners: usize, ratio: f64) -> usize {
+    ((listeners as f64 * ratio).ceil() as usize).max(1)
+}
+
+// ramps the track's volume, stopping early if the track is gone
+async fn fade(track_handle: &TrackHandle, from: f32, to: f32, duration: Duration) {
+    let steps = (duration.as_millis() / config::audio::FADE_STEP_INTERVAL.as_millis()).max(1);
+    for step in 1..=steps {
+        sleep(config::audio::FADE_STEP_INTERVAL).await;
+        let volume = from + (to - from) * step as f32 / steps as f32;
+        if track_handle.set_volume(volume).is_err() {
+            return;
+        }
+    }
+}
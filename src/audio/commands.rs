@@ -1,13 +1,26 @@
 use super::{
+    audio_cache::AudioCache,
     audio_state::AudioState,
-    types::{self, QueuePosition},
+    config,
+    filters::{EqPreset, FilterChain},
+    library::Library,
+    permissions::{self, is_dj, PermissionLevel},
+    queue_view::{queue_view_contents, run_queue_view},
+    settings::SettingsDb,
+    song_picker::show_song_picker,
+    types::{self, QueuePosition, SkipOutcome},
 };
 use anyhow::{anyhow, Context};
-use poise::{serenity_prelude::CacheHttp, ChoiceParameter, Command};
-use songbird::tracks::TrackHandle;
-use std::sync::Arc;
+use poise::{
+    serenity_prelude::{Attachment, CacheHttp, Role, UserId},
+    ChoiceParameter, Command, CreateReply,
+};
+use std::{path::Path, sync::Arc, time::Duration};
 
-use crate::{util::send_embed, Data, Error, PoiseContext};
+use crate::{
+    util::{get_styled_embed, send_embed},
+    Data, Error, PoiseContext,
+};
 
 async fn get_audio_state(ctx: &PoiseContext<'_>) -> anyhow::Result<Arc<AudioState>> {
     // let ctx = Arc::new(ctx.clone());
@@ -52,7 +65,26 @@ async fn get_audio_state(ctx: &PoiseContext<'_>) -> anyhow::Result<Arc<AudioStat
         }
         None => {
             let handle_lock = manager.join(guild_id, channel_id).await?;
-            let audio_state = AudioState::new(handle_lock, ctx);
+            let (settings, cache) = {
+                let data = ctx.serenity_context().data.read().await;
+                let settings = data
+                    .get::<SettingsDb>()
+                    .context("SettingsDb object was not initialized in serenity TypeMap")?
+                    .get(guild_id);
+                let cache = data
+                    .get::<AudioCache>()
+                    .context("AudioCache object was not initialized in serenity TypeMap")?
+                    .clone();
+                (settings, cache)
+            };
+            let audio_state = AudioState::new(
+                handle_lock,
+                guild_id,
+                settings,
+                cache,
+                Arc::new(ctx.serenity_context().clone()),
+                ctx.channel_id(),
+            );
             {
                 let mut audio_states = ctx.data().audio_states.lock().await;
                 audio_states.insert(guild_id, audio_state.clone());
@@ -62,6 +94,77 @@ async fn get_audio_state(ctx: &PoiseContext<'_>) -> anyhow::Result<Arc<AudioStat
     }
 }
 
+// enforces the guild's permission policy before any command runs
+pub async fn check_command_permission(ctx: PoiseContext<'_>) -> anyhow::Result<bool, Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return Ok(true),
+    };
+    let member = ctx
+        .author_member()
+        .await
+        .context("failed to get guild member")?;
+    permissions::check_permission(
+        ctx.serenity_context(),
+        guild_id,
+        ctx.channel_id(),
+        &member,
+        &ctx.command().qualified_name,
+    )
+    .await?;
+    Ok(true)
+}
+
+async fn author_is_dj(ctx: &PoiseContext<'_>) -> anyhow::Result<bool> {
+    let guild_id = ctx.guild_id().context("failed to get guild id")?;
+    let policy = permissions::get_policy(ctx.serenity_context(), guild_id).await?;
+    Ok(match ctx.author_member().await {
+        Some(member) => is_dj(
+            ctx.serenity_context(),
+            guild_id,
+            ctx.channel_id(),
+            &member,
+            &policy,
+        ),
+        None => false,
+    })
+}
+
+// DJs may remove any song, everyone else only the songs they requested
+async fn removable_requester(ctx: &PoiseContext<'_>) -> anyhow::Result<Option<UserId>> {
+    match author_is_dj(ctx).await? {
+        true => Ok(None),
+        false => Ok(Some(ctx.author().id)),
+    }
+}
+
+async fn update_permission_policy<F: FnOnce(&mut permissions::PermissionPolicy)>(
+    ctx: &PoiseContext<'_>,
+    f: F,
+) -> anyhow::Result<()> {
+    let guild_id = ctx.guild_id().context("failed to get guild id")?;
+    let settings = {
+        let mut data = ctx.serenity_context().data.write().await;
+        data.get_mut::<SettingsDb>()
+            .context("SettingsDb object was not initialized in serenity TypeMap")?
+            .update_and_flush(guild_id, |settings| f(&mut settings.permissions))?
+    };
+    send_embed(
+        ctx.http(),
+        ctx.channel_id(),
+        &settings.permissions.get_string(),
+    )
+    .await?;
+    Ok(())
+}
+
+// shows the permission policy without changing it
+async fn show_permission_policy(ctx: &PoiseContext<'_>) -> anyhow::Result<()> {
+    let guild_id = ctx.guild_id().context("failed to get guild id")?;
+    let policy = permissions::get_policy(ctx.serenity_context(), guild_id).await?;
+    send_embed(ctx.http(), ctx.channel_id(), &policy.get_string()).await
+}
+
 async fn remove_audio_state(ctx: &PoiseContext<'_>) -> anyhow::Result<(), Error> {
     let guild_id = ctx.guild_id().context("failed to get guild id")?;
 
@@ -101,6 +204,8 @@ async fn exit(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
 enum StreamType {
     Online,
     Loudnorm,
+    Dynaudnorm,
+    PeakNorm,
 }
 
 impl From<StreamType> for types::StreamType {
@@ -108,6 +213,8 @@ impl From<StreamType> for types::StreamType {
         match val {
             StreamType::Online => types::StreamType::Online,
             StreamType::Loudnorm => types::StreamType::Loudnorm,
+            StreamType::Dynaudnorm => types::StreamType::Dynaudnorm,
+            StreamType::PeakNorm => types::StreamType::PeakNorm,
         }
     }
 }
@@ -125,7 +232,34 @@ async fn play(
         false => types::StreamType::Online,
     };
     audio_state
-        .add_audio(&query, QueuePosition::default(), shuffle, loudnorm)
+        .add_audio(
+            &query,
+            QueuePosition::default(),
+            shuffle,
+            loudnorm,
+            ctx.author().id,
+        )
+        .await?;
+    audio_state
+        .display_ui_with_poise_context_reply(&ctx)
+        .await?;
+    Ok(())
+}
+
+/// Play audio files uploaded to discord
+#[poise::command(prefix_command, slash_command)]
+async fn play_file(
+    ctx: PoiseContext<'_>,
+    #[description = "mp3, ogg, flac or other audio file"] file: Attachment,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    // prefix commands may have several files attached to the message
+    let attachments = match ctx {
+        poise::Context::Prefix(prefix_ctx) => prefix_ctx.msg.attachments.clone(),
+        poise::Context::Application(_) => vec![file],
+    };
+    audio_state
+        .add_attachments(&attachments, QueuePosition::default(), ctx.author().id)
         .await?;
     audio_state
         .display_ui_with_poise_context_reply(&ctx)
@@ -142,7 +276,9 @@ async fn recommend(
 ) -> anyhow::Result<(), Error> {
     let audio_state = get_audio_state(&ctx).await?;
     let amount = amount.parse().context("invalid integer")?;
-    audio_state.add_recommended_songs(&query, amount).await?;
+    audio_state
+        .add_recommended_songs(&query, amount, ctx.author().id)
+        .await?;
     audio_state
         .display_ui_with_poise_context_reply(&ctx)
         .await?;
@@ -159,7 +295,9 @@ async fn extend(
     let audio_state = get_audio_state(&ctx).await?;
 
     let extend_ratio = ratio.parse().context("invalid ratio")?;
-    audio_state.extend_songs(&query, extend_ratio).await?;
+    audio_state
+        .extend_songs(&query, extend_ratio, ctx.author().id)
+        .await?;
     audio_state
         .display_ui_with_poise_context_reply(&ctx)
         .await?;
@@ -170,13 +308,315 @@ async fn extend(
 #[poise::command(prefix_command, slash_command)]
 async fn skip(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
     let audio_state = get_audio_state(&ctx).await?;
-    audio_state.send_track_command(TrackHandle::stop).await?;
+    let can_bypass = author_is_dj(&ctx).await?;
+    if let SkipOutcome::Voted { votes, required } =
+        audio_state.vote_skip(ctx.author().id, can_bypass).await?
+    {
+        send_embed(
+            ctx.serenity_context().http(),
+            ctx.channel_id(),
+            &format!("Voted to skip: {votes}/{required}"),
+        )
+        .await?;
+    }
+    audio_state
+        .display_ui_with_poise_context_reply(&ctx)
+        .await?;
+    Ok(())
+}
+
+// parses timestamps of the form "ss", "mm:ss" or "hh:mm:ss"
+fn parse_timestamp(timestamp: &str) -> anyhow::Result<Duration> {
+    let parts = timestamp
+        .split(':')
+        .map(|part| part.trim().parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("invalid timestamp {timestamp}"))?;
+    if parts.is_empty() || parts.len() > 3 {
+        return Err(anyhow!("invalid timestamp {timestamp}"));
+    }
+    let secs = parts.iter().fold(0, |acc, part| acc * 60 + part);
+    Ok(Duration::from_secs(secs))
+}
+
+/// Seeks to a position in the current song
+#[poise::command(prefix_command, slash_command)]
+async fn seek(
+    ctx: PoiseContext<'_>,
+    #[description = "position to seek to, e.g. 1:30"] position: String,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    audio_state.seek(parse_timestamp(&position)?).await?;
+    audio_state
+        .display_ui_with_poise_context_reply(&ctx)
+        .await?;
+    Ok(())
+}
+
+/// Sets the playback volume
+#[poise::command(prefix_command, slash_command)]
+async fn volume(
+    ctx: PoiseContext<'_>,
+    #[description = "volume in percent, between 0 and 200"] volume: u32,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    audio_state.set_volume(volume).await?;
+    send_embed(
+        ctx.serenity_context().http(),
+        ctx.channel_id(),
+        &format!("Volume: {volume}%"),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Sets how long songs crossfade into each other, 0 disables crossfading
+#[poise::command(prefix_command, slash_command)]
+async fn crossfade(
+    ctx: PoiseContext<'_>,
+    #[description = "crossfade duration in seconds"] secs: u64,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    audio_state.set_crossfade(secs).await?;
+    send_embed(
+        ctx.serenity_context().http(),
+        ctx.channel_id(),
+        &format!("Crossfade: {secs}s"),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Sets how long the bot stays in a voice channel without listeners before disconnecting
+#[poise::command(prefix_command, slash_command)]
+async fn idle_timeout(
+    ctx: PoiseContext<'_>,
+    #[description = "idle timeout in seconds"] secs: u64,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    audio_state.set_idle_timeout(secs).await?;
+    send_embed(
+        ctx.serenity_context().http(),
+        ctx.channel_id(),
+        &format!("Idle timeout: {secs}s"),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Enables or disables vote skipping, where skips need votes from a fraction of the listeners
+#[poise::command(prefix_command, slash_command)]
+async fn vote_skip(
+    ctx: PoiseContext<'_>,
+    #[description = "enable vote skipping?"] enabled: bool,
+    #[description = "fraction of listeners needed to skip, e.g. 0.5"] ratio: Option<f64>,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    audio_state.set_vote_skip(enabled, ratio).await?;
+    let settings = audio_state.get_settings().await;
+    send_embed(
+        ctx.serenity_context().http(),
+        ctx.channel_id(),
+        &format!(
+            "Vote skip: {} (ratio {})",
+            settings.vote_skip, settings.vote_skip_ratio
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Enables or disables the fair queue, which plays songs from each requester in turn
+#[poise::command(prefix_command, slash_command)]
+async fn fair_queue(
+    ctx: PoiseContext<'_>,
+    #[description = "enable the fair queue?"] enabled: bool,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    audio_state.set_fair_queue(enabled).await?;
+    send_embed(
+        ctx.serenity_context().http(),
+        ctx.channel_id(),
+        &format!("Fair queue: {enabled}"),
+    )
+    .await?;
     audio_state
         .display_ui_with_poise_context_reply(&ctx)
         .await?;
     Ok(())
 }
 
+/// Searches or rescans the local music library
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands("library_search", "library_rescan")
+)]
+async fn library(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
+    let data = ctx.serenity_context().data.read().await;
+    let library = data
+        .get::<Library>()
+        .context("Library object was not initialized in serenity TypeMap")?;
+    let text = match library.root() {
+        Some(root) => format!("{} tracks in {root:?}", library.num_tracks()),
+        None => "No music library is configured".to_string(),
+    };
+    ctx.send(CreateReply::default().embed(get_styled_embed(&text)))
+        .await?;
+    Ok(())
+}
+
+/// Lists library tracks matching the query, to pick songs from
+#[poise::command(prefix_command, slash_command, rename = "search")]
+async fn library_search(
+    ctx: PoiseContext<'_>,
+    #[rest]
+    #[description = "artist, title, album or file name"]
+    query: String,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    let records = {
+        let data = ctx.serenity_context().data.read().await;
+        data.get::<Library>()
+            .context("Library object was not initialized in serenity TypeMap")?
+            .search(
+                &query,
+                config::audio::LIBRARY_SEARCH_RESULTS,
+                audio_state.get_stream_type().await,
+            )
+    };
+    show_song_picker(&ctx, &audio_state, "Library:", records).await?;
+    Ok(())
+}
+
+/// Indexes the music library again, e.g. after files were added
+#[poise::command(prefix_command, slash_command, rename = "rescan")]
+async fn library_rescan(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
+    let root = {
+        let data = ctx.serenity_context().data.read().await;
+        data.get::<Library>()
+            .context("Library object was not initialized in serenity TypeMap")?
+            .root()
+            .map(Path::to_path_buf)
+            .context("no music library is configured")?
+    };
+    ctx.defer().await?;
+    let library = tokio::task::spawn_blocking(|| Library::index(Some(root))).await??;
+    let text = format!("Indexed {} tracks", library.num_tracks());
+    ctx.serenity_context()
+        .data
+        .write()
+        .await
+        .insert::<Library>(library);
+    ctx.send(CreateReply::default().embed(get_styled_embed(&text)))
+        .await?;
+    Ok(())
+}
+
+/// Shows or changes what songs are normalized to. Applies to songs loaded from now on
+#[poise::command(prefix_command, slash_command)]
+async fn loudnorm_targets(
+    ctx: PoiseContext<'_>,
+    #[description = "integrated loudness in LUFS, e.g. -16"] integrated: Option<f64>,
+    #[description = "loudness range in LU, e.g. 11"] lra: Option<f64>,
+    #[description = "true peak in dBTP, also used by peaknorm, e.g. -1.5"] true_peak: Option<f64>,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    let mut targets = audio_state.get_settings().await.loudnorm_targets;
+    let ranges = [
+        (
+            integrated,
+            &mut targets.integrated,
+            "integrated",
+            config::audio::LOUDNORM_INTEGRATED_RANGE,
+        ),
+        (
+            lra,
+            &mut targets.lra,
+            "lra",
+            config::audio::LOUDNORM_LRA_RANGE,
+        ),
+        (
+            true_peak,
+            &mut targets.true_peak,
+            "true_peak",
+            config::audio::LOUDNORM_TRUE_PEAK_RANGE,
+        ),
+    ];
+    for (value, target, name, (min, max)) in ranges {
+        if let Some(value) = value {
+            *target = check_range(name, value, min, max)?;
+        }
+    }
+    audio_state.set_loudnorm_targets(targets).await?;
+    send_embed(ctx.http(), ctx.channel_id(), &targets.get_string()).await?;
+    Ok(())
+}
+
+/// Shows or changes the limits on what can be added to the queue. 0 means unlimited
+#[poise::command(prefix_command, slash_command)]
+async fn queue_policy(
+    ctx: PoiseContext<'_>,
+    #[description = "max number of songs in the queue"] max_queue_length: Option<usize>,
+    #[description = "max number of queued songs per user"] max_songs_per_user: Option<usize>,
+    #[description = "max track duration, e.g. 1:30:00"] max_track_duration: Option<String>,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    let mut policy = audio_state.get_settings().await.queue_policy;
+    let unlimited_if_zero = |limit| match limit {
+        0 => None,
+        limit => Some(limit),
+    };
+    if let Some(max) = max_queue_length {
+        policy.max_queue_length = unlimited_if_zero(max);
+    }
+    if let Some(max) = max_songs_per_user {
+        policy.max_songs_per_user = unlimited_if_zero(max);
+    }
+    if let Some(max) = max_track_duration {
+        policy.max_track_duration_secs = match parse_timestamp(&max)?.as_secs() {
+            0 => None,
+            secs => Some(secs),
+        };
+    }
+    audio_state.set_queue_policy(policy.clone()).await?;
+    send_embed(
+        ctx.serenity_context().http(),
+        ctx.channel_id(),
+        &policy.get_string(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Re-queues the most recently played song at the front of the queue
+#[poise::command(prefix_command, slash_command)]
+async fn previous(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    let song = audio_state.previous(ctx.author().id).await?;
+    send_embed(
+        ctx.serenity_context().http(),
+        ctx.channel_id(),
+        &format!("Re-queued: {song}"),
+    )
+    .await?;
+    audio_state
+        .display_ui_with_poise_context_reply(&ctx)
+        .await?;
+    Ok(())
+}
+
+/// Lists recently played songs
+#[poise::command(prefix_command, slash_command)]
+async fn history(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    let records = audio_state
+        .get_history(config::audio::HISTORY_DISPLAY_LENGTH)
+        .await;
+    show_song_picker(&ctx, &audio_state, "Recently played:", records).await?;
+    Ok(())
+}
+
 /// Play or pause the audio player
 #[poise::command(prefix_command, slash_command)]
 async fn pause_resume(
@@ -213,25 +653,164 @@ async fn clear(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
     Ok(())
 }
 
-/// Sets the current song to loop / not loop
+/// Removes a song from the queue
+#[poise::command(prefix_command, slash_command)]
+async fn remove(
+    ctx: PoiseContext<'_>,
+    #[description = "position of the song in the queue"] position: usize,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    let requester = removable_requester(&ctx).await?;
+    let song = audio_state.remove(position, requester).await?;
+    send_embed(
+        ctx.serenity_context().http(),
+        ctx.channel_id(),
+        &format!("Removed: {song}"),
+    )
+    .await?;
+    audio_state
+        .display_ui_with_poise_context_reply(&ctx)
+        .await?;
+    Ok(())
+}
+
+/// Removes all songs between two queue positions (inclusive)
+#[poise::command(prefix_command, slash_command)]
+async fn remove_range(
+    ctx: PoiseContext<'_>,
+    #[description = "position of the first song to remove"] start: usize,
+    #[description = "position of the last song to remove"] end: usize,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    let requester = removable_requester(&ctx).await?;
+    let removed = audio_state.remove_range(start, end, requester).await?;
+    send_embed(
+        ctx.serenity_context().http(),
+        ctx.channel_id(),
+        &format!("Removed {removed} songs"),
+    )
+    .await?;
+    audio_state
+        .display_ui_with_poise_context_reply(&ctx)
+        .await?;
+    Ok(())
+}
+
+/// Moves a song to a different position in the queue
+#[poise::command(prefix_command, slash_command, rename = "move")]
+async fn move_song(
+    ctx: PoiseContext<'_>,
+    #[description = "current position of the song"] from: usize,
+    #[description = "new position of the song"] to: usize,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    audio_state.move_to(from, to).await?;
+    audio_state
+        .display_ui_with_poise_context_reply(&ctx)
+        .await?;
+    Ok(())
+}
+
+/// Swaps the positions of two songs in the queue
+#[poise::command(prefix_command, slash_command)]
+async fn swap(
+    ctx: PoiseContext<'_>,
+    #[description = "position of the first song"] a: usize,
+    #[description = "position of the second song"] b: usize,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    audio_state.swap(a, b).await?;
+    audio_state
+        .display_ui_with_poise_context_reply(&ctx)
+        .await?;
+    Ok(())
+}
+
+/// Skips ahead to the song at the given queue position
+#[poise::command(prefix_command, slash_command)]
+async fn skip_to(
+    ctx: PoiseContext<'_>,
+    #[description = "position of the song to play next"] position: usize,
+) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    let can_bypass = author_is_dj(&ctx).await?;
+    if let SkipOutcome::Voted { votes, required } = audio_state
+        .skip_to(position, ctx.author().id, can_bypass)
+        .await?
+    {
+        send_embed(
+            ctx.serenity_context().http(),
+            ctx.channel_id(),
+            &format!("Voted to skip: {votes}/{required}"),
+        )
+        .await?;
+    }
+    audio_state
+        .display_ui_with_poise_context_reply(&ctx)
+        .await?;
+    Ok(())
+}
+
+#[derive(Copy, Clone, ChoiceParameter)]
+enum LoopMode {
+    Off,
+    Track,
+    Queue,
+}
+
+impl From<LoopMode> for types::LoopMode {
+    fn from(val: LoopMode) -> Self {
+        match val {
+            LoopMode::Off => types::LoopMode::Off,
+            LoopMode::Track => types::LoopMode::Track,
+            LoopMode::Queue => types::LoopMode::Queue,
+        }
+    }
+}
+
+/// Sets the loop mode: "off", "track" or "queue". Cycles through the modes if none is given
 #[poise::command(prefix_command, slash_command)]
 async fn looping(
     ctx: PoiseContext<'_>,
-    #[description = "Whether to loop (y) or not loop (n)"] b: bool,
+    #[description = "Allowed values: \"off\", \"track\" or \"queue\""] mode: Option<LoopMode>,
 ) -> anyhow::Result<(), Error> {
     let audio_state = get_audio_state(&ctx).await?;
-    audio_state.change_looping(Some(b)).await?;
+    let mode = audio_state.change_looping(mode.map(Into::into)).await?;
+    send_embed(
+        ctx.serenity_context().http(),
+        ctx.channel_id(),
+        &format!("Loop mode: {mode}"),
+    )
+    .await?;
     audio_state
         .display_ui_with_poise_context_reply(&ctx)
         .await?;
     Ok(())
 }
 
-/// Changes the stream type: allowed values are "online" or "loudnorm"
+/// Re-queues the songs that failed to load
+#[poise::command(prefix_command, slash_command, rename = "retry-failed")]
+async fn retry_failed(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
+    let audio_state = get_audio_state(&ctx).await?;
+    let retried = audio_state.retry_failed().await?;
+    send_embed(
+        ctx.serenity_context().http(),
+        ctx.channel_id(),
+        &format!("Retrying {retried} songs"),
+    )
+    .await?;
+    audio_state
+        .display_ui_with_poise_context_reply(&ctx)
+        .await?;
+    Ok(())
+}
+
+/// Changes the stream type: allowed values are "online", "loudnorm", "dynaudnorm" or "peaknorm"
 #[poise::command(prefix_command, slash_command)]
 async fn stream_type(
     ctx: PoiseContext<'_>,
-    #[description = "Allowed values: \"online\" or \"loudnorm\" "] query: StreamType,
+    #[description = "Allowed values: \"online\", \"loudnorm\", \"dynaudnorm\" or \"peaknorm\" "]
+    query: StreamType,
 ) -> anyhow::Result<(), Error> {
     let audio_state = get_audio_state(&ctx).await?;
     audio_state.change_stream_type(query.into()).await;
@@ -248,28 +827,317 @@ async fn queue(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
     audio_state
         .display_ui_with_poise_context_reply(&ctx)
         .await?;
+    let (embed, components) = queue_view_contents(&audio_state).await;
+    let handle = ctx
+        .send(CreateReply::default().embed(embed).components(components))
+        .await?;
+    run_queue_view(
+        ctx.serenity_context(),
+        handle.into_message().await?,
+        &audio_state,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn send_filters(ctx: &PoiseContext<'_>, filters: &FilterChain) -> anyhow::Result<()> {
     send_embed(
-        ctx.serenity_context().http(),
+        ctx.http(),
         ctx.channel_id(),
-        &audio_state.get_string().await,
+        &format!("**Filters:**\n{}", filters.get_string()),
+    )
+    .await
+}
+
+// changes the filters of the current guild, and shows the result
+async fn update_filters<F: FnOnce(&mut FilterChain)>(
+    ctx: &PoiseContext<'_>,
+    f: F,
+) -> anyhow::Result<()> {
+    let audio_state = get_audio_state(ctx).await?;
+    let filters = audio_state.update_filters(f).await?;
+    send_filters(ctx, &filters).await?;
+    audio_state.display_ui_with_poise_context_reply(ctx).await?;
+    Ok(())
+}
+
+// shows the filters without touching playback
+async fn show_filters(ctx: &PoiseContext<'_>) -> anyhow::Result<()> {
+    let audio_state = get_audio_state(ctx).await?;
+    send_filters(ctx, &audio_state.get_settings().await.filters).await
+}
+
+fn check_range(name: &str, value: f64, min: f64, max: f64) -> anyhow::Result<f64> {
+    match (min..=max).contains(&value) {
+        true => Ok(value),
+        false => Err(anyhow!("{name} must be between {min} and {max}")),
+    }
+}
+
+/// Shows or changes the audio filters, which apply to every song
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands(
+        "filter_show",
+        "bass",
+        "treble",
+        "eq",
+        "speed",
+        "pitch",
+        "nightcore",
+        "rotate",
+        "filter_reset"
     )
+)]
+async fn filter(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
+    show_filters(&ctx).await?;
+    Ok(())
+}
+
+/// Shows the audio filters
+#[poise::command(prefix_command, slash_command, rename = "show")]
+async fn filter_show(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
+    show_filters(&ctx).await?;
+    Ok(())
+}
+
+/// Boosts or cuts the bass, 0 disables
+#[poise::command(prefix_command, slash_command)]
+async fn bass(
+    ctx: PoiseContext<'_>,
+    #[description = "gain in dB"] gain: f64,
+) -> anyhow::Result<(), Error> {
+    let max = config::audio::MAX_FILTER_GAIN_DB;
+    let gain = check_range("gain", gain, -max, max)?;
+    update_filters(&ctx, |filters| filters.bass_gain = gain).await?;
+    Ok(())
+}
+
+/// Boosts or cuts the treble, 0 disables
+#[poise::command(prefix_command, slash_command)]
+async fn treble(
+    ctx: PoiseContext<'_>,
+    #[description = "gain in dB"] gain: f64,
+) -> anyhow::Result<(), Error> {
+    let max = config::audio::MAX_FILTER_GAIN_DB;
+    let gain = check_range("gain", gain, -max, max)?;
+    update_filters(&ctx, |filters| filters.treble_gain = gain).await?;
+    Ok(())
+}
+
+/// Sets the equalizer preset
+#[poise::command(prefix_command, slash_command)]
+async fn eq(
+    ctx: PoiseContext<'_>,
+    #[description = "equalizer preset"] preset: EqPresetChoice,
+) -> anyhow::Result<(), Error> {
+    update_filters(&ctx, |filters| filters.eq_preset = preset.into()).await?;
+    Ok(())
+}
+
+/// Changes the playback speed without changing the pitch, 1 is the original speed
+#[poise::command(prefix_command, slash_command)]
+async fn speed(
+    ctx: PoiseContext<'_>,
+    #[description = "speed multiplier"] speed: f64,
+) -> anyhow::Result<(), Error> {
+    let speed = check_range(
+        "speed",
+        speed,
+        config::audio::MIN_FILTER_SPEED,
+        config::audio::MAX_FILTER_SPEED,
+    )?;
+    update_filters(&ctx, |filters| filters.speed = speed).await?;
+    Ok(())
+}
+
+/// Changes the pitch without changing the speed, 0 is the original pitch
+#[poise::command(prefix_command, slash_command)]
+async fn pitch(
+    ctx: PoiseContext<'_>,
+    #[description = "semitones to shift by"] semitones: f64,
+) -> anyhow::Result<(), Error> {
+    let max = config::audio::MAX_FILTER_PITCH;
+    let semitones = check_range("semitones", semitones, -max, max)?;
+    update_filters(&ctx, |filters| filters.pitch = semitones).await?;
+    Ok(())
+}
+
+/// Enables or disables nightcore, which speeds up the audio and raises its pitch
+#[poise::command(prefix_command, slash_command)]
+async fn nightcore(
+    ctx: PoiseContext<'_>,
+    #[description = "enable nightcore?"] enabled: bool,
+) -> anyhow::Result<(), Error> {
+    update_filters(&ctx, |filters| filters.nightcore = enabled).await?;
+    Ok(())
+}
+
+/// Pans the audio around the listener (8D audio), 0 disables
+#[poise::command(prefix_command, slash_command)]
+async fn rotate(
+    ctx: PoiseContext<'_>,
+    #[description = "rotations per second"] hz: f64,
+) -> anyhow::Result<(), Error> {
+    let hz = check_range("hz", hz, 0.0, config::audio::MAX_FILTER_ROTATION_HZ)?;
+    update_filters(&ctx, |filters| filters.rotation_hz = hz).await?;
+    Ok(())
+}
+
+/// Disables every filter
+#[poise::command(prefix_command, slash_command, rename = "reset")]
+async fn filter_reset(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
+    update_filters(&ctx, |filters| *filters = FilterChain::default()).await?;
+    Ok(())
+}
+
+#[derive(Copy, Clone, ChoiceParameter)]
+enum EqPresetChoice {
+    Flat,
+    Pop,
+    Rock,
+    Jazz,
+    Classical,
+    Electronic,
+    Vocal,
+}
+
+impl From<EqPresetChoice> for EqPreset {
+    fn from(val: EqPresetChoice) -> Self {
+        match val {
+            EqPresetChoice::Flat => EqPreset::Flat,
+            EqPresetChoice::Pop => EqPreset::Pop,
+            EqPresetChoice::Rock => EqPreset::Rock,
+            EqPresetChoice::Jazz => EqPreset::Jazz,
+            EqPresetChoice::Classical => EqPreset::Classical,
+            EqPresetChoice::Electronic => EqPreset::Electronic,
+            EqPresetChoice::Vocal => EqPreset::Vocal,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ChoiceParameter)]
+enum PermissionLevelChoice {
+    Everyone,
+    Dj,
+    Admin,
+}
+
+impl From<PermissionLevelChoice> for PermissionLevel {
+    fn from(val: PermissionLevelChoice) -> Self {
+        match val {
+            PermissionLevelChoice::Everyone => PermissionLevel::Everyone,
+            PermissionLevelChoice::Dj => PermissionLevel::Dj,
+            PermissionLevelChoice::Admin => PermissionLevel::Admin,
+        }
+    }
+}
+
+/// Shows or configures who may use which commands. Only admins may use this
+#[poise::command(
+    prefix_command,
+    slash_command,
+    rename = "permissions",
+    subcommands("show", "dj_role", "command_level", "same_voice_channel")
+)]
+async fn permissions_command(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
+    show_permission_policy(&ctx).await?;
+    Ok(())
+}
+
+/// Shows the permission policy
+#[poise::command(prefix_command, slash_command)]
+async fn show(ctx: PoiseContext<'_>) -> anyhow::Result<(), Error> {
+    show_permission_policy(&ctx).await?;
+    Ok(())
+}
+
+/// Sets the DJ role, or removes it if none is given
+#[poise::command(prefix_command, slash_command)]
+async fn dj_role(
+    ctx: PoiseContext<'_>,
+    #[description = "the DJ role"] role: Option<Role>,
+) -> anyhow::Result<(), Error> {
+    update_permission_policy(&ctx, |policy| policy.dj_role = role.map(|role| role.id)).await?;
+    Ok(())
+}
+
+// whether the qualified name, e.g. "filter bass", is a command or subcommand
+fn is_known_command(commands: &[Command<Data, Error>], name: &str) -> bool {
+    commands.iter().any(|command| {
+        command.qualified_name == name || is_known_command(&command.subcommands, name)
+    })
+}
+
+/// Sets who may use a command
+#[poise::command(prefix_command, slash_command)]
+async fn command_level(
+    ctx: PoiseContext<'_>,
+    #[description = "name of the command, e.g. \"filter bass\""] command: String,
+    #[description = "who may use the command"] level: PermissionLevelChoice,
+) -> anyhow::Result<(), Error> {
+    let command = command.split_whitespace().collect::<Vec<_>>().join(" ");
+    if !is_known_command(&ctx.framework().options().commands, &command)
+        || permissions::is_permissions_command(&command)
+    {
+        return Err(anyhow!("cannot set the permission level of \"{command}\"").into());
+    }
+    let level: PermissionLevel = level.into();
+    update_permission_policy(&ctx, |policy| {
+        if level == policy.inherited_level(&command) {
+            policy.command_levels.remove(&command);
+        } else {
+            policy.command_levels.insert(command, level);
+        }
+    })
     .await?;
     Ok(())
 }
 
+/// Sets whether only users in the bot's voice channel may control it
+#[poise::command(prefix_command, slash_command)]
+async fn same_voice_channel(
+    ctx: PoiseContext<'_>,
+    #[description = "only allow users in the voice channel?"] enabled: bool,
+) -> anyhow::Result<(), Error> {
+    update_permission_policy(&ctx, |policy| policy.require_same_voice_channel = enabled).await?;
+    Ok(())
+}
+
 pub fn add_group(commands: &mut Vec<Command<Data, Error>>) {
     commands.extend(vec![
         start(),
         exit(),
         play(),
+        play_file(),
         recommend(),
         extend(),
         skip(),
+        seek(),
+        volume(),
+        crossfade(),
+        idle_timeout(),
+        vote_skip(),
+        fair_queue(),
+        queue_policy(),
+        library(),
+        filter(),
+        loudnorm_targets(),
+        previous(),
+        history(),
         pause_resume(),
         shuffle(),
         clear(),
+        remove(),
+        remove_range(),
+        move_song(),
+        swap(),
+        skip_to(),
         looping(),
         stream_type(),
+        retry_failed(),
         queue(),
+        permissions_command(),
     ])
 }
@@ -9,18 +9,74 @@ pub mod audio {
 
     //pub const EXTEND_RATIO: f64 = 1.5;
     // pub const TIMEOUT_DURATION: Duration = Duration::from_millis(600000);
-    pub const AUDIO_LOOP_POLL_INTERVAL: Duration = Duration::from_millis(1000);
-    pub const SONG_LOADER_POLL_INTERVAL: Duration = Duration::from_millis(1000);
     pub const YTDL_QUERY_RETRY_INTERVAL: Duration = Duration::from_millis(5000);
     pub const YTDL_DOWNLOAD_RETRY_INTERVAL: Duration = Duration::from_millis(10000);
     pub const GET_AUDIO_READER_NUM_RETRIES: usize = 3;
+    // maximum number of songs loaded at the same time, per guild, unless set by the environment
+    pub const DEFAULT_SONG_LOADER_CONCURRENCY: usize = 3;
+    // only this many songs at the front of the queue are loaded ahead of time
+    pub const SONG_LOADER_LOAD_AHEAD: usize = 10;
+    // youtube source urls stop working after a few hours, so songs looped after this are reloaded
+    pub const ONLINE_SOURCE_URL_TTL: Duration = Duration::from_secs(3600);
     //pub const AUDIO_NORM_DB: i32 = -10;
     pub const BOT_PREFIX: &str = "o.";
     pub const MESSAGE_UI_COMPONENT_CHAIN_INTERVAL_MS: u64 = 500;
+    // discord rate limits message edits, so don't refresh too often
+    pub const NOW_PLAYING_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+    pub const NOW_PLAYING_PROGRESS_BAR_WIDTH: usize = 20;
+    pub const SEEK_STEP_SECS: i64 = 10;
+    pub const MAX_VOLUME: u32 = 200;
+    pub const VOLUME_STEP: i64 = 10;
+    pub const MAX_CROSSFADE_SECS: u64 = 15;
+    pub const CROSSFADE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+    // fade applied when pausing, resuming and skipping
+    pub const FADE_DURATION: Duration = Duration::from_millis(300);
+    pub const FADE_STEP_INTERVAL: Duration = Duration::from_millis(50);
+    pub const REMOVE_SONGS_MENU_TIMEOUT: Duration = Duration::from_secs(60);
+    pub const SONG_PICKER_TIMEOUT: Duration = Duration::from_secs(300);
+    pub const PLAY_HISTORY_LENGTH: usize = 50;
+    pub const HISTORY_DISPLAY_LENGTH: usize = 10;
+    pub const FAILED_SONGS_LENGTH: usize = 50;
+    pub const MAX_LISTED_REJECTIONS: usize = 10;
+    pub const QUEUE_PAGE_SIZE: usize = 10;
+    pub const QUEUE_VIEW_TIMEOUT: Duration = Duration::from_secs(300);
+    pub const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+    pub const SESSION_RESTORE_TIMEOUT: Duration = Duration::from_secs(3600);
+    // files in the music library with other extensions are not indexed
+    pub const LIBRARY_EXTENSIONS: [&str; 7] = ["mp3", "flac", "ogg", "wav", "m4a", "aac", "mp4"];
+    pub const LIBRARY_SEARCH_RESULTS: usize = 10;
+    // in bytes
+    pub const MAX_ATTACHMENT_SIZE: u32 = 50 * 1024 * 1024;
+    // in bytes, read from the start of attachments for their tags. Leaves room for cover art
+    pub const ATTACHMENT_PROBE_SIZE: usize = 1024 * 1024;
+    pub const STREAM_TITLE_POLL_INTERVAL: Duration = Duration::from_secs(15);
+    // in bytes. Streams usually send metadata every 8-16KB of audio
+    pub const MAX_ICY_METAINT: usize = 1024 * 1024;
+    pub const URL_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+    pub const MAX_URL_REDIRECTS: usize = 5;
+    // in bytes, for audio files linked directly
+    pub const MAX_DIRECT_URL_SIZE: u64 = 50 * 1024 * 1024;
+    pub const MAX_FILTER_GAIN_DB: f64 = 20.0;
+    // ffmpeg's atempo filter supports speeds between 0.5 and 2 without chaining
+    pub const MIN_FILTER_SPEED: f64 = 0.5;
+    pub const MAX_FILTER_SPEED: f64 = 2.0;
+    // pitch shifting is done with atempo too, which limits it to an octave
+    pub const MAX_FILTER_PITCH: f64 = 12.0;
+    pub const MAX_FILTER_ROTATION_HZ: f64 = 2.0;
+    // the ranges accepted by ffmpeg's loudnorm filter
+    pub const LOUDNORM_INTEGRATED_RANGE: (f64, f64) = (-70.0, -5.0);
+    pub const LOUDNORM_LRA_RANGE: (f64, f64) = (1.0, 20.0);
+    pub const LOUDNORM_TRUE_PEAK_RANGE: (f64, f64) = (-9.0, 0.0);
+    // in bytes. The least recently played songs are deleted from the cache past this size
+    pub const AUDIO_CACHE_MAX_SIZE: u64 = 2 * 1024 * 1024 * 1024;
 }
 
 pub mod env {
     pub const DISCORD_BOT_TOKEN: &str = "OCTAVE_BOT_TOKEN";
     pub const SPOTIFY_CLIENT_ID: &str = "SPOTIFY_CLIENT_ID";
     pub const SPOTIFY_CLIENT_SECRET: &str = "SPOTIFY_CLIENT_SECRET";
+    // directory of audio files to index, the library is empty if unset
+    pub const MUSIC_LIBRARY_DIR: &str = "OCTAVE_MUSIC_LIBRARY";
+    // maximum number of songs loaded at the same time, per guild
+    pub const SONG_LOADER_CONCURRENCY: &str = "OCTAVE_SONG_LOADER_CONCURRENCY";
 }
@@ -1,6 +1,7 @@
 use std::{collections::BTreeMap, fs};
 
 use anyhow::Context;
+use serde::{de::DeserializeOwned, Serialize};
 use serenity::prelude::TypeMapKey;
 
 type Data = BTreeMap<String, String>;
@@ -10,21 +11,27 @@ pub struct Db {
     data: Data,
 }
 
-fn save_data(path: &str, data: &Data) -> anyhow::Result<()> {
+// the json files of every db are read and written the same way. name is used in errors
+pub(super) fn save_data<T: Serialize>(path: &str, name: &str, data: &T) -> anyhow::Result<()> {
     let data = serde_json::to_string_pretty(data)?;
-    fs::write(path, data).context("failed to write db")
+    fs::write(path, data).with_context(|| format!("failed to write {name}"))
 }
 
-fn load_or_init_data(path: &str) -> anyhow::Result<Data> {
+pub(super) fn load_or_init_data<T: Serialize + DeserializeOwned + Default>(
+    path: &str,
+    name: &str,
+) -> anyhow::Result<T> {
     let data_exists = fs::exists(path).context("failed to check existence of file")?;
     match data_exists {
         true => {
-            let data = fs::read_to_string(path).context("failed to load db")?;
-            serde_json::from_str(&data).context("failed to deserialize data from db")
+            let data =
+                fs::read_to_string(path).with_context(|| format!("failed to load {name}"))?;
+            serde_json::from_str(&data)
+                .with_context(|| format!("failed to deserialize data from {name}"))
         }
         false => {
-            let data = BTreeMap::new();
-            save_data(path, &data)?;
+            let data = T::default();
+            save_data(path, name, &data)?;
             Ok(data)
         }
     }
@@ -32,7 +39,7 @@ fn load_or_init_data(path: &str) -> anyhow::Result<Data> {
 
 impl Db {
     pub fn new(path: String) -> anyhow::Result<Self> {
-        let data = load_or_init_data(&path)?;
+        let data = load_or_init_data(&path, "db")?;
         Ok(Self { data, path })
     }
 
@@ -42,7 +49,7 @@ impl Db {
 
     pub fn insert_and_flush(&mut self, key: String, value: String) -> anyhow::Result<()> {
         self.data.insert(key, value);
-        save_data(&self.path, &self.data)
+        save_data(&self.path, "db", &self.data)
     }
 }
 
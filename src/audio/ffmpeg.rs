@@ -1,16 +1,22 @@
 use crate::audio::config;
 
 use super::{
-    types::{AudioReaderConfig, StreamType},
+    audio_cache::{AudioCache, CachedTrack},
+    icy,
+    types::{AudioReaderConfig, AudioSource, LoadFailure, LoudnormTargets, StreamType},
     ytdl,
 };
 use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
 use songbird::input::core::io::MediaSource;
 use std::{
-    io::{BufReader, Cursor},
+    ffi::OsString,
+    io::{BufReader, Cursor, Write},
+    path::PathBuf,
     process::{Command, Stdio},
     str,
-    time::Instant,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
 };
 use symphonia::core::io::ReadOnlySource;
 use tokio::{
@@ -19,37 +25,303 @@ use tokio::{
     time::timeout,
 };
 
-#[derive(Clone)]
-struct LoudnormConfig {
+#[derive(Clone, Serialize, Deserialize)]
+pub(super) struct LoudnormConfig {
     integrated: f64,
     true_peak: f64,
     lra: f64,
     threshold: f64,
 }
 
+// where ffmpeg reads a song's audio from
+enum FfmpegInput {
+    Url(String),
+    File(PathBuf),
+}
+
+impl FfmpegInput {
+    // network streams are reconnected if the connection drops, but ffmpeg rejects these options
+    // for local files
+    fn args(&self, start: Duration) -> Vec<OsString> {
+        let start = format!("{:.3}", start.as_secs_f64());
+        let mut args: Vec<OsString> = match self {
+            Self::Url(_) => [
+                "-reconnect",
+                "1",
+                "-reconnect_streamed",
+                "1",
+                "-reconnect_delay_max",
+                "5",
+            ]
+            .map(OsString::from)
+            .to_vec(),
+            _ => vec![],
+        };
+        args.push("-ss".into());
+        args.push(start.into());
+        args.push("-i".into());
+        args.push(match self {
+            Self::Url(url) => url.into(),
+            Self::File(path) => path.into(),
+        });
+        args
+    }
+
+    // the config for streaming the input without normalization
+    fn into_config(self) -> AudioReaderConfig {
+        match self {
+            Self::Url(src_url) => AudioReaderConfig::Online { src_url },
+            Self::File(path) => AudioReaderConfig::LocalFile { path },
+        }
+    }
+}
+
+// where get_audio_reader's ffmpeg reads audio from
+enum ReaderInput {
+    Source(FfmpegInput),
+    // downloaded audio written to ffmpeg's stdin
+    Pipe(Vec<u8>),
+    // a direct url, streamed to ffmpeg's stdin as it is received
+    Http(reqwest::Response),
+}
+
+impl ReaderInput {
+    // the input and filter args. start is a position in the song, before any change of speed by
+    // the filters
+    fn args(&self, start: Duration, filters: Option<&str>) -> Vec<OsString> {
+        let (mut args, filters) = match self {
+            Self::Source(input) => (input.args(start), filters.map(str::to_string)),
+            // pipes can't be seeked, so the audio before start is decoded and dropped instead.
+            // This is done ahead of the filters, which may change the speed
+            Self::Pipe(_) | Self::Http(_) => {
+                let trim = format!(
+                    "atrim=start={:.3},asetpts=PTS-STARTPTS",
+                    start.as_secs_f64()
+                );
+                let filters = match filters {
+                    Some(filters) => format!("{trim},{filters}"),
+                    None => trim,
+                };
+                (vec!["-i".into(), "pipe:0".into()], Some(filters))
+            }
+        };
+        if let Some(filters) = filters {
+            args.push("-af".into());
+            args.push(filters.into());
+        }
+        args
+    }
+}
+
+// how audio is normalized after it is downloaded, which is every stream type but online
+#[derive(Copy, Clone)]
+enum Normalization {
+    Loudnorm,
+    Dynaudnorm,
+    PeakNorm,
+}
+
+impl Normalization {
+    fn of(stream_type: StreamType) -> Option<Self> {
+        match stream_type {
+            StreamType::Online => None,
+            StreamType::Loudnorm => Some(Self::Loudnorm),
+            StreamType::Dynaudnorm => Some(Self::Dynaudnorm),
+            StreamType::PeakNorm => Some(Self::PeakNorm),
+        }
+    }
+
+    // identifies how cached audio was normalized
+    fn cache_variant(self, targets: LoudnormTargets) -> String {
+        match self {
+            Self::Loudnorm => format!(
+                "loudnorm:{:.1}:{:.1}:{:.1}",
+                targets.integrated, targets.lra, targets.true_peak
+            ),
+            Self::Dynaudnorm => "dynaudnorm".to_string(),
+            Self::PeakNorm => format!("peaknorm:{:.1}", targets.true_peak),
+        }
+    }
+}
+
+// a direct url downloaded to a temporary file, which is deleted when this is dropped
+struct DownloadFile {
+    path: PathBuf,
+}
+
+impl DownloadFile {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let name = format!(
+            "octave-{}-{}.download",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        );
+        Self {
+            path: std::env::temp_dir().join(name),
+        }
+    }
+}
+
+impl Drop for DownloadFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// None if the audio isn't cached
+async fn load_cached(
+    cache: &AudioCache,
+    identity: &str,
+    variant: &str,
+    max_duration: Option<Duration>,
+) -> Option<Result<(AudioReaderConfig, Option<Duration>), LoadFailure>> {
+    let track = cache.get_track(identity).await?;
+    if let (Some(duration), Some(max)) = (track.duration, max_duration) {
+        if duration > max {
+            return Some(Err(LoadFailure::TooLong { duration, max }));
+        }
+    }
+    let buf = cache.get_audio(identity, variant).await?;
+    Some(Ok((AudioReaderConfig::Loudnorm { buf }, track.duration)))
+}
+
+// also returns the duration of the audio, if known. Audio longer than max_duration is rejected
+// before it is downloaded. Normalized audio is cached, so repeats skip downloading and ffmpeg
 pub async fn get_audio_reader_config(
-    ytdl_query: &str,
+    source: &AudioSource,
     stream_type: StreamType,
-) -> anyhow::Result<AudioReaderConfig> {
-    let src_url = timeout(
-        config::audio::YTDL_QUERY_RETRY_INTERVAL,
-        ytdl::ytdl_get_source_url(ytdl_query),
-    )
-    .await?;
-    match stream_type {
-        StreamType::Online => Ok(AudioReaderConfig::Online { src_url }),
-        StreamType::Loudnorm => {
-            let buf = timeout(
+    max_duration: Option<Duration>,
+    targets: LoudnormTargets,
+    cache: &AudioCache,
+) -> Result<(AudioReaderConfig, Option<Duration>), LoadFailure> {
+    let ffmpeg_failure = |why: anyhow::Error| LoadFailure::Ffmpeg(why.to_string());
+    let normalization = Normalization::of(stream_type);
+    let variant = normalization.map(|normalization| normalization.cache_variant(targets));
+    let known_identity = cache.identity(source).await;
+    if let (Some(variant), Some(identity)) = (&variant, &known_identity) {
+        if let Some(res) = load_cached(cache, identity, variant, max_duration).await {
+            return res;
+        }
+    }
+    // deleted once the audio is loaded
+    let mut download = None;
+    let (input, duration, identity) = match source {
+        AudioSource::Ytdl(query) => {
+            let ytdl::YtdlSource {
+                src_url,
+                duration,
+                track_id,
+            } = timeout(
+                config::audio::YTDL_QUERY_RETRY_INTERVAL,
+                ytdl::ytdl_get_source(query),
+            )
+            .await
+            .map_err(|_| LoadFailure::Timeout)?
+            .map_err(|why| LoadFailure::YtDlp(why.to_string()))?;
+            let identity = AudioCache::ytdl_identity(&track_id);
+            // a different query may have found the same track before
+            if let Some(variant) = &variant {
+                if let Some(res) = load_cached(cache, &identity, variant, max_duration).await {
+                    cache.remember_query(query, &identity).await;
+                    return res;
+                }
+            }
+            (FfmpegInput::Url(src_url), duration, Some(identity))
+        }
+        // the duration of audio files is already known from their tags
+        AudioSource::Url(url) => {
+            if variant.is_none() {
+                return Ok((AudioReaderConfig::DirectUrl { url: url.clone() }, None));
+            }
+            // the checked download is converted from a file, since some formats can't be read
+            // from a pipe
+            let file = download.insert(DownloadFile::new());
+            timeout(
                 config::audio::YTDL_DOWNLOAD_RETRY_INTERVAL,
-                download_audio_buf(src_url.clone()),
+                icy::download_audio(url, &file.path, config::audio::MAX_DIRECT_URL_SIZE),
+            )
+            .await
+            .map_err(|_| LoadFailure::Timeout)?
+            .map_err(|why| LoadFailure::Download(format!("{why:#}")))?;
+            (FfmpegInput::File(file.path.clone()), None, known_identity)
+        }
+        AudioSource::LocalFile(path) => (FfmpegInput::File(path.clone()), None, known_identity),
+    };
+    if let (Some(duration), Some(max)) = (duration, max_duration) {
+        if duration > max {
+            return Err(LoadFailure::TooLong { duration, max });
+        }
+    }
+    let (Some(normalization), Some(variant)) = (normalization, variant) else {
+        return Ok((input.into_config(), duration));
+    };
+    // direct links may point at anything, so they are limited like attachments
+    let max_size = match source {
+        AudioSource::Url(_) => Some(config::audio::MAX_DIRECT_URL_SIZE),
+        _ => None,
+    };
+    let buf = timeout(
+        config::audio::YTDL_DOWNLOAD_RETRY_INTERVAL,
+        download_audio_buf(&input, max_size),
+    )
+    .await
+    .map_err(|_| LoadFailure::Timeout)?
+    .map_err(ffmpeg_failure)?;
+    let mut loudnorm = match &identity {
+        Some(identity) => cache
+            .get_track(identity)
+            .await
+            .and_then(|track| track.loudnorm),
+        None => None,
+    };
+    let filter = match normalization {
+        Normalization::Loudnorm => {
+            if loudnorm.is_none() {
+                let measured = get_loudnorm_params(buf.clone(), targets)
+                    .await
+                    .map_err(ffmpeg_failure)?;
+                loudnorm = Some(measured);
+            }
+            loudnorm_filter(targets, loudnorm.as_ref())
+        }
+        Normalization::Dynaudnorm => "dynaudnorm".to_string(),
+        Normalization::PeakNorm => {
+            let max_volume = ffmpeg_get_max_volume(buf.clone())
+                .await
+                .map_err(ffmpeg_failure)?;
+            format!("volume={:.2}dB", targets.true_peak - max_volume)
+        }
+    };
+    let buf = ffmpeg_filter_convert(buf, filter)
+        .await
+        .map_err(ffmpeg_failure)?;
+    if let Some(identity) = identity {
+        let track = CachedTrack { duration, loudnorm };
+        cache.insert(&identity, track, &variant, &buf).await;
+        if let AudioSource::Ytdl(query) = source {
+            cache.remember_query(query, &identity).await;
+        }
+    }
+    Ok((AudioReaderConfig::Loudnorm { buf }, duration))
+}
+
+// the second pass of loudnorm uses the values measured by the first pass
+fn loudnorm_filter(targets: LoudnormTargets, measured: Option<&LoudnormConfig>) -> String {
+    let mut filter = format!(
+        "loudnorm=I={:.1}:LRA={:.1}:TP={:.1}",
+        targets.integrated, targets.lra, targets.true_peak
+    );
+    match measured {
+        Some(measured) => {
+            filter += &format!(
+                ":measured_I={:.2}:measured_LRA={:.2}:measured_TP={:.2}:measured_thresh={:.2}",
+                measured.integrated, measured.lra, measured.true_peak, measured.threshold
             )
-            .await??;
-            let loudnorm = get_loudnorm_params(buf.clone()).await?;
-            let buf = ffmpeg_loudnorm_convert(buf, loudnorm).await?;
-            // let volume_delta = ffmpeg_get_volume(&buf).await?;
-            Ok(AudioReaderConfig::Loudnorm { buf })
         }
+        None => filter += ":print_format=summary",
     }
+    filter
 }
 
 /*
@@ -76,26 +348,43 @@ async fn download_audio(mut url: String) -> Result<Vec<u8>, String> {
 // static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(reqwest::Client::new);
 
 //todo: do this in-process using HLS
-async fn download_audio_buf(url: String) -> anyhow::Result<Vec<u8>> {
+// fails if the converted audio is larger than max_size bytes
+async fn download_audio_buf(input: &FfmpegInput, max_size: Option<u64>) -> anyhow::Result<Vec<u8>> {
     let now = Instant::now();
     let mut cmd = TokioCommand::new("ffmpeg");
-    let out = cmd
-        .arg("-reconnect")
-        .arg("1")
-        .arg("-reconnect_streamed")
-        .arg("1")
-        .arg("-reconnect_delay_max")
-        .arg("5")
-        .arg("-i")
-        .arg(url)
+    let mut child = cmd
+        .args(input.args(Duration::ZERO))
         .arg("-f")
         .arg("mp3")
         .arg("pipe:1")
-        .output()
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        // stops the download once the limit is reached
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to run ffmpeg")?;
+    let stdout = child.stdout.take().context("failed to get ffmpeg stdout")?;
+    let mut buf = vec![];
+    stdout
+        .take(max_size.map_or(u64::MAX, |max| max + 1))
+        .read_to_end(&mut buf)
         .await
-        .unwrap();
+        .context("failed to read audio from ffmpeg")?;
+    if let Some(max) = max_size {
+        if buf.len() as u64 > max {
+            return Err(anyhow!(
+                "audio is larger than the max of {} MB",
+                max / (1024 * 1024)
+            ));
+        }
+    }
+    child.wait().await.context("failed to wait for ffmpeg")?;
     log::info!("audio downloaded, time: {:?}", now.elapsed());
-    Ok(out.stdout)
+    if buf.is_empty() {
+        return Err(anyhow!("failed to download audio"));
+    }
+    Ok(buf)
 }
 
 fn pipe_to_stdin_async(buf: Vec<u8>, mut stdin: ChildStdin, context: &'static str) {
@@ -114,19 +403,24 @@ fn pipe_to_stdin_async(buf: Vec<u8>, mut stdin: ChildStdin, context: &'static st
     tokio::task::spawn(future);
 }
 
-async fn get_loudnorm_params(buf: Vec<u8>) -> anyhow::Result<LoudnormConfig> {
+async fn get_loudnorm_params(
+    buf: Vec<u8>,
+    targets: LoudnormTargets,
+) -> anyhow::Result<LoudnormConfig> {
     let mut cmd = TokioCommand::new("ffmpeg");
     let cmd = cmd
         .arg("-i")
         .arg("pipe:0")
         .arg("-af")
-        .arg("loudnorm=I=-16:LRA=11:TP=-1.5:print_format=summary")
+        .arg(loudnorm_filter(targets, None))
         .arg("-vn")
         .arg("-sn")
         .arg("-dn")
         .arg("-f")
         .arg("mp3")
         .arg("-")
+        // killed when the load is aborted, e.g. because the song was removed
+        .kill_on_drop(true)
         .stdin(Stdio::piped())
         .stdout(Stdio::null())
         .stderr(Stdio::piped());
@@ -174,47 +468,50 @@ fn _parse_loudnorm_params(buf: &str, target: &str) -> anyhow::Result<f64> {
     }
 }
 
-// async fn ffmpeg_get_volume(buf: &[u8]) -> anyhow::Result<f64> {
-//     let mut cmd = TokioCommand::new("ffmpeg");
-//     let cmd = cmd
-//         .arg("-f")
-//         .arg("mp3")
-//         .arg("-i")
-//         .arg("pipe:0")
-//         .arg("-af")
-//         .arg("volumedetect")
-//         .arg("-vn")
-//         .arg("-sn")
-//         .arg("-dn")
-//         .arg("-f")
-//         .arg("mp3")
-//         .arg("-")
-//         .stdin(Stdio::piped())
-//         .stdout(Stdio::null())
-//         .stderr(Stdio::piped());
-//     let mut child = cmd.spawn().context("failed to spawn child")?;
-//     let stdin = child.stdin.as_mut().unwrap();
-
-//     if let Err(why) = stdin.write_all(buf).await {
-//         log::warn!("warning: subprocess::ffmpeg_get_volume error: {}", why);
-//     };
+// the peak volume of the audio in dB, 0 being the loudest possible
+async fn ffmpeg_get_max_volume(buf: Vec<u8>) -> anyhow::Result<f64> {
+    let mut cmd = TokioCommand::new("ffmpeg");
+    let cmd = cmd
+        .arg("-i")
+        .arg("pipe:0")
+        .arg("-af")
+        .arg("volumedetect")
+        .arg("-vn")
+        .arg("-sn")
+        .arg("-dn")
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        // killed when the load is aborted, e.g. because the song was removed
+        .kill_on_drop(true)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let child = cmd.spawn().context("failed to spawn child")?;
+    let stdin = child.stdin.unwrap();
+    pipe_to_stdin_async(buf, stdin, "subprocess::ffmpeg_get_max_volume");
 
-//     let out = child
-//         .wait_with_output()
-//         .await
-//         .context("failed to wait for child output")?;
-//     let out = str::from_utf8(&out.stderr).context("failed to parse str from utf8")?;
-//     let split = out
-//         .split("max_volume: ")
-//         .nth(1)
-//         .context("ffmpeg volumedetect output not recognised")?;
-//     match split.split(' ').next() {
-//         Some(vol) => Ok(-(vol.parse::<f64>().unwrap())),
-//         None => Err(anyhow::anyhow!(
-//             "ffmpeg volumedetect output failed to parse as integer".to_string()
-//         )),
-//     }
-// }
+    let mut buf = vec![];
+    child
+        .stderr
+        .unwrap()
+        .read_to_end(&mut buf)
+        .await
+        .context("failed to wait for child stderr")?;
+    let out = str::from_utf8(&buf).context("failed to parse str from utf8")?;
+    let split = out
+        .split("max_volume: ")
+        .nth(1)
+        .context("ffmpeg volumedetect output not recognised")?;
+    match split.split(' ').next() {
+        Some(volume) => volume
+            .parse::<f64>()
+            .context("ffmpeg volumedetect output failed to parse as float"),
+        None => Err(anyhow::anyhow!(
+            "ffmpeg volumedetect output failed to parse as float"
+        )),
+    }
+}
 
 // async fn pipe_stdin(buf: &[u8], mut pipe: ChildStdin) {
 //     if let Err(x) = pipe.write_all(buf).await {
@@ -222,18 +519,14 @@ fn _parse_loudnorm_params(buf: &str, target: &str) -> anyhow::Result<f64> {
 //     };
 // }
 
-async fn ffmpeg_loudnorm_convert(
-    buf: Vec<u8>,
-    loudnorm: LoudnormConfig,
-) -> anyhow::Result<Vec<u8>> {
-    let loudnorm_string = format!("loudnorm=I=-16:LRA=11:TP=-1.5:measured_I={:.2}:measured_LRA={:.2}:measured_TP={:.2}:measured_thresh={:.2}", 
-        loudnorm.integrated, loudnorm.lra, loudnorm.true_peak, loudnorm.threshold);
+// converts the audio to 48kHz stereo mp3, applying the audio filter
+async fn ffmpeg_filter_convert(buf: Vec<u8>, filter: String) -> anyhow::Result<Vec<u8>> {
     let mut cmd = TokioCommand::new("ffmpeg");
     let cmd = cmd
         .arg("-i")
         .arg("pipe:0")
         .arg("-af")
-        .arg(loudnorm_string)
+        .arg(filter)
         .arg("-vn")
         .arg("-sn")
         .arg("-dn")
@@ -244,12 +537,14 @@ async fn ffmpeg_loudnorm_convert(
         .arg("-f")
         .arg("mp3")
         .arg("-")
+        // killed when the load is aborted, e.g. because the song was removed
+        .kill_on_drop(true)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::inherit());
     let child = cmd.spawn().context("failed to spawn child")?;
     let stdin = child.stdin.unwrap();
-    pipe_to_stdin_async(buf, stdin, "subprocess::ffmpeg_loudnorm_convert");
+    pipe_to_stdin_async(buf, stdin, "subprocess::ffmpeg_filter_convert");
 
     let mut buf = vec![];
     child
@@ -311,45 +606,17 @@ async fn ffmpeg_loudnorm_convert(
     Ok(buf)
 }*/
 
-// for loudnorm, requires existing, downloaded buffer
+// for loudnorm, requires existing, downloaded buffer. Without filters, start is ignored for
+// loudnorm, since the returned source is seekable. filters is an ffmpeg audio filter graph
 pub async fn get_audio_reader(
     config: AudioReaderConfig,
+    start: Duration,
+    filters: Option<String>,
 ) -> anyhow::Result<Box<dyn MediaSource + Send>> {
-    let mut cmd = Command::new("ffmpeg");
-    match config {
-        AudioReaderConfig::Online { src_url } => {
-            // songbird supports synchronous IO only, or a synchronous wrapper around async IO,
-            // hence we're not using TokioCommand
-            let cmd = cmd
-                .arg("-reconnect")
-                .arg("1")
-                .arg("-reconnect_streamed")
-                .arg("1")
-                .arg("-reconnect_delay_max")
-                .arg("5")
-                .arg("-i")
-                .arg(src_url)
-                .arg("-f")
-                // .arg("s16le")
-                .arg("mp3")
-                //.arg("-af").arg("loudnorm")
-                .arg("-ar")
-                .arg("48000")
-                .arg("-ac")
-                .arg("2")
-                // .arg("-acodec")
-                // .arg("pcm_f32le")
-                .arg("pipe:1")
-                .stdout(Stdio::piped())
-                .stderr(Stdio::null());
-            let child = cmd.spawn().context("failed to spawn child")?;
-
-            let stdout = child
-                .stdout
-                .context("subprocess::get_audio_reader: failed to get child stdout")?;
-            let buf = BufReader::with_capacity(16384 * 32 * 32, stdout);
-            Ok(Box::new(ReadOnlySource::new(buf)))
-        }
+    let input = match config {
+        AudioReaderConfig::Online { src_url } => ReaderInput::Source(FfmpegInput::Url(src_url)),
+        AudioReaderConfig::LocalFile { path } => ReaderInput::Source(FfmpegInput::File(path)),
+        AudioReaderConfig::DirectUrl { url } => ReaderInput::Http(icy::request_audio(&url).await?),
         //todo: consider whether one-pass loudnorm is enough. that way we can cut-through stream audio for loudnorm instead of downloading all at once.
         AudioReaderConfig::Loudnorm { buf } => {
             // cmd
@@ -372,11 +639,79 @@ pub async fn get_audio_reader(
             //     .stdin(Stdio::piped())
             //     .stdout(Stdio::piped())
             //     .stderr(Stdio::null()),
-            let reader = Cursor::new(buf);
-            Ok(Box::new(ReadOnlySource::new(reader)))
+            match filters {
+                // a bare cursor is seekable, which allows seeking within the track
+                None => return Ok(Box::new(Cursor::new(buf))),
+                // the filters are applied by piping the buffer through ffmpeg
+                Some(_) => ReaderInput::Pipe(buf),
+            }
+        }
+    };
+    // songbird supports synchronous IO only, or a synchronous wrapper around async IO,
+    // hence we're not using TokioCommand
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(input.args(start, filters.as_deref()));
+    let cmd = cmd
+        .arg("-f")
+        // .arg("s16le")
+        .arg("mp3")
+        //.arg("-af").arg("loudnorm")
+        .arg("-ar")
+        .arg("48000")
+        .arg("-ac")
+        .arg("2")
+        // .arg("-acodec")
+        // .arg("pcm_f32le")
+        .arg("pipe:1")
+        .stdin(match input {
+            ReaderInput::Pipe(_) | ReaderInput::Http(_) => Stdio::piped(),
+            ReaderInput::Source(_) => Stdio::null(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    let mut child = cmd.spawn().context("failed to spawn child")?;
+    // writing fails once the track is stopped and ffmpeg exits, which is expected
+    match input {
+        ReaderInput::Source(_) => (),
+        ReaderInput::Pipe(buf) => {
+            let mut stdin = child
+                .stdin
+                .take()
+                .context("subprocess::get_audio_reader: failed to get child stdin")?;
+            std::thread::spawn(move || {
+                if let Err(why) = stdin.write_all(&buf) {
+                    log::debug!("subprocess::get_audio_reader stdin error: {}", why);
+                }
+            });
+        }
+        ReaderInput::Http(mut response) => {
+            let mut stdin = child
+                .stdin
+                .take()
+                .context("subprocess::get_audio_reader: failed to get child stdin")?;
+            let runtime = tokio::runtime::Handle::current();
+            std::thread::spawn(move || loop {
+                let chunk = match runtime.block_on(response.chunk()) {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => return,
+                    Err(why) => {
+                        log::warn!("subprocess::get_audio_reader download error: {}", why);
+                        return;
+                    }
+                };
+                if let Err(why) = stdin.write_all(&chunk) {
+                    log::debug!("subprocess::get_audio_reader stdin error: {}", why);
+                    return;
+                }
+            });
         }
-        AudioReaderConfig::Error => Err(anyhow!("error loading audio, skipping")),
     }
+
+    let stdout = child
+        .stdout
+        .context("subprocess::get_audio_reader: failed to get child stdout")?;
+    let buf = BufReader::with_capacity(16384 * 32 * 32, stdout);
+    Ok(Box::new(ReadOnlySource::new(buf)))
 }
 
 /*
@@ -428,3 +763,53 @@ pub fn ffmpeg_pcm(url: String) -> Result<Box<dyn MediaSource + Send>, String> {
 fn ffmpeg(url: &str){
 
 }*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::filters::FilterChain;
+
+    fn args_strings(input: &ReaderInput, start: Duration, filters: Option<&str>) -> Vec<String> {
+        input
+            .args(start, filters)
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn pipe_is_trimmed_before_speed_filters() {
+        let filters = FilterChain {
+            speed: 1.5,
+            nightcore: true,
+            ..FilterChain::default()
+        }
+        .to_ffmpeg();
+        let args = args_strings(
+            &ReaderInput::Pipe(vec![]),
+            Duration::from_secs(30),
+            filters.as_deref(),
+        );
+        // an output -ss would be measured after the speed change
+        assert!(!args.contains(&"-ss".to_string()));
+        assert_eq!(args[..3], ["-i", "pipe:0", "-af"]);
+        let graph = &args[3];
+        assert!(graph.starts_with("atrim=start=30.000,asetpts=PTS-STARTPTS,"));
+        let trim = graph.find("atrim").unwrap();
+        assert!(trim < graph.find("asetrate").unwrap());
+        assert!(trim < graph.find("atempo").unwrap());
+    }
+
+    #[test]
+    fn sources_are_seeked_before_the_input() {
+        let args = args_strings(
+            &ReaderInput::Source(FfmpegInput::File("song.mp3".into())),
+            Duration::from_millis(1500),
+            Some("atempo=1.5000"),
+        );
+        assert_eq!(
+            args,
+            ["-ss", "1.500", "-i", "song.mp3", "-af", "atempo=1.5000"]
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// the sample rate that audio is played at
+const SAMPLE_RATE: u32 = 48000;
+// nightcore speeds up the audio and raises its pitch by the same factor
+const NIGHTCORE_RATE: f64 = 1.25;
+
+#[derive(Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum EqPreset {
+    #[default]
+    Flat,
+    Pop,
+    Rock,
+    Jazz,
+    Classical,
+    Electronic,
+    Vocal,
+}
+
+impl EqPreset {
+    // gains in dB at 60Hz, 230Hz, 910Hz, 3.6kHz and 14kHz
+    fn band_gains(self) -> [f64; 5] {
+        match self {
+            Self::Flat => [0.0; 5],
+            Self::Pop => [-1.0, 2.0, 4.0, 2.0, -1.0],
+            Self::Rock => [4.0, 2.0, -2.0, 2.0, 4.0],
+            Self::Jazz => [3.0, 1.0, -1.0, 1.0, 3.0],
+            Self::Classical => [3.0, 1.0, 0.0, 1.0, 3.0],
+            Self::Electronic => [5.0, 2.0, 0.0, 2.0, 4.0],
+            Self::Vocal => [-2.0, -1.0, 3.0, 3.0, 0.0],
+        }
+    }
+}
+
+impl fmt::Display for EqPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Flat => "flat",
+            Self::Pop => "pop",
+            Self::Rock => "rock",
+            Self::Jazz => "jazz",
+            Self::Classical => "classical",
+            Self::Electronic => "electronic",
+            Self::Vocal => "vocal",
+        };
+        f.write_str(name)
+    }
+}
+
+const EQ_BANDS_HZ: [u32; 5] = [60, 230, 910, 3600, 14000];
+
+// audio effects applied by ffmpeg while playing. The defaults leave the audio unchanged
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilterChain {
+    // in dB, 0 disables the filter
+    pub bass_gain: f64,
+    pub treble_gain: f64,
+    pub eq_preset: EqPreset,
+    // playback speed without changing the pitch, 1 is the original speed
+    pub speed: f64,
+    // in semitones, without changing the speed
+    pub pitch: f64,
+    pub nightcore: bool,
+    // how often the audio pans around the listener per second, 0 disables the 8D effect
+    pub rotation_hz: f64,
+}
+
+impl Default for FilterChain {
+    fn default() -> Self {
+        Self {
+            bass_gain: 0.0,
+            treble_gain: 0.0,
+            eq_preset: EqPreset::Flat,
+            speed: 1.0,
+            pitch: 0.0,
+            nightcore: false,
+            rotation_hz: 0.0,
+        }
+    }
+}
+
+impl FilterChain {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // how much faster than the original the audio plays, which maps track positions to song
+    // positions
+    pub fn speed_factor(&self) -> f64 {
+        match self.nightcore {
+            true => self.speed * NIGHTCORE_RATE,
+            false => self.speed,
+        }
+    }
+
+    // the ffmpeg audio filter graph, or None if no filters are enabled
+    pub fn to_ffmpeg(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        // changing the sample rate requires knowing it first
+        let mut filters = vec![format!("aresample={SAMPLE_RATE}")];
+        if self.bass_gain != 0.0 {
+            filters.push(format!("bass=g={:.1}", self.bass_gain));
+        }
+        if self.treble_gain != 0.0 {
+            filters.push(format!("treble=g={:.1}", self.treble_gain));
+        }
+        for (freq, gain) in EQ_BANDS_HZ.iter().zip(self.eq_preset.band_gains()) {
+            if gain != 0.0 {
+                filters.push(format!("equalizer=f={freq}:t=o:w=1:g={gain:.1}"));
+            }
+        }
+        if self.pitch != 0.0 {
+            // resampling changes both pitch and speed, so the speed is changed back
+            let ratio = 2f64.powf(self.pitch / 12.0);
+            filters.push(format!(
+                "asetrate={:.0},aresample={SAMPLE_RATE},atempo={:.4}",
+                SAMPLE_RATE as f64 * ratio,
+                1.0 / ratio
+            ));
+        }
+        if self.nightcore {
+            filters.push(format!(
+                "asetrate={:.0},aresample={SAMPLE_RATE}",
+                SAMPLE_RATE as f64 * NIGHTCORE_RATE
+            ));
+        }
+        if self.speed != 1.0 {
+            filters.push(format!("atempo={:.4}", self.speed));
+        }
+        if self.rotation_hz != 0.0 {
+            filters.push(format!("apulsator=hz={:.3}", self.rotation_hz));
+        }
+        Some(filters.join(","))
+    }
+
+    pub fn get_string(&self) -> String {
+        if self.is_empty() {
+            return "*no filters*".to_string();
+        }
+        let mut res = vec![];
+        if self.bass_gain != 0.0 {
+            res.push(format!("**Bass:** {:+.1} dB", self.bass_gain));
+        }
+        if self.treble_gain != 0.0 {
+            res.push(format!("**Treble:** {:+.1} dB", self.treble_gain));
+        }
+        if self.eq_preset != EqPreset::Flat {
+            res.push(format!("**EQ:** {}", self.eq_preset));
+        }
+        if self.speed != 1.0 {
+            res.push(format!("**Speed:** {:.2}x", self.speed));
+        }
+        if self.pitch != 0.0 {
+            res.push(format!("**Pitch:** {:+.1} semitones", self.pitch));
+        }
+        if self.nightcore {
+            res.push("**Nightcore**".to_string());
+        }
+        if self.rotation_hz != 0.0 {
+            res.push(format!("**8D:** {:.2} Hz", self.rotation_hz));
+        }
+        res.join("\n")
+    }
+}
@@ -0,0 +1,327 @@
+// internet radio streams (icecast/shoutcast) interleave "ICY" metadata, such as the title of the
+// song being played, with the audio when requested by the Icy-MetaData header
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context};
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::HeaderMap,
+    redirect::{Attempt, Policy},
+    ClientBuilder, Response, Url,
+};
+use tokio::io::AsyncWriteExt;
+use url::Host;
+
+use super::config;
+
+// what the response headers of an audio url tell about it
+pub struct UrlInfo {
+    // live streams never end, e.g. internet radio
+    pub is_live: bool,
+    // the name of the radio station, if any
+    pub name: Option<String>,
+}
+
+fn get_header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+// addresses that are reachable from the internet, as opposed to the bot's own network
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast())
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+// hosts that are ip addresses don't go through the resolver, so are checked separately
+fn is_public_host_literal(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Ipv4(ip)) => is_public(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_public(IpAddr::V6(ip)),
+        Some(Host::Domain(domain)) => domain != "localhost",
+        None => false,
+    }
+}
+
+// resolves hosts to public addresses only. The client connects to exactly the addresses that
+// were checked, so a host can't resolve to a different address afterwards, and redirects to
+// other hosts are checked too
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            // the port is replaced by the url's port
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+                return Err(anyhow!("{} is not a public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// direct urls are requested by the bot, so they must not point into its own network
+fn check_public_url(url: &Url) -> anyhow::Result<()> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow!("only http and https urls are supported"));
+    }
+    let host = url.host_str().context("url has no host")?;
+    match is_public_host_literal(url) {
+        true => Ok(()),
+        false => Err(anyhow!("{host} is not a public address")),
+    }
+}
+
+fn redirect_policy(attempt: Attempt) -> reqwest::redirect::Action {
+    if attempt.previous().len() >= config::audio::MAX_URL_REDIRECTS {
+        attempt.error("too many redirects")
+    } else if !is_public_host_literal(attempt.url()) {
+        attempt.error("redirected to a non-public address")
+    } else {
+        attempt.follow()
+    }
+}
+
+// every request for a direct url goes through this, and never through ffmpeg, so that the
+// checks apply each time the url is loaded. with_icy_metadata asks streams to interleave metadata
+// with the audio
+async fn request(
+    url: &str,
+    client: ClientBuilder,
+    with_icy_metadata: bool,
+) -> anyhow::Result<Response> {
+    let parsed = Url::parse(url).with_context(|| format!("invalid url {url}"))?;
+    check_public_url(&parsed)?;
+    let mut request = client
+        .connect_timeout(config::audio::URL_REQUEST_TIMEOUT)
+        .redirect(Policy::custom(redirect_policy))
+        .dns_resolver(Arc::new(PublicResolver))
+        // a proxy would resolve hosts itself
+        .no_proxy()
+        .build()
+        .context("failed to build http client")?
+        .get(parsed);
+    if with_icy_metadata {
+        request = request.header("Icy-MetaData", "1");
+    }
+    request
+        .send()
+        .await
+        .with_context(|| format!("failed to request {url}"))?
+        .error_for_status()
+        .context("failed to request audio url")
+}
+
+// a request that only reads a little of the response, so may not take long in total
+async fn request_metadata(url: &str) -> anyhow::Result<Response> {
+    let client = reqwest::Client::builder().timeout(config::audio::URL_REQUEST_TIMEOUT);
+    request(url, client, true).await
+}
+
+// the audio of a direct url, which may be a stream that never ends
+pub async fn request_audio(url: &str) -> anyhow::Result<Response> {
+    let client = reqwest::Client::builder().read_timeout(config::audio::URL_REQUEST_TIMEOUT);
+    request(url, client, false).await
+}
+
+// fails if the file is larger than max_size bytes, e.g. because the url is a stream
+pub async fn download_audio(url: &str, path: &Path, max_size: u64) -> anyhow::Result<()> {
+    let mut response = request_audio(url).await?;
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .context("failed to create download file")?;
+    let mut size = 0;
+    while let Some(chunk) = response.chunk().await? {
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err(anyhow!(
+                "{url} is larger than the max of {} MB",
+                max_size / (1024 * 1024)
+            ));
+        }
+        file.write_all(&chunk)
+            .await
+            .context("failed to write download file")?;
+    }
+    file.flush()
+        .await
+        .context("failed to write download file")?;
+    Ok(())
+}
+
+// only reads the response headers, the body is dropped
+pub async fn probe_url(url: &str) -> anyhow::Result<UrlInfo> {
+    let response = request_metadata(url).await?;
+    let headers = response.headers();
+    let is_icy = headers.keys().any(|name| name.as_str().starts_with("icy-"));
+    let content_type = get_header(headers, "content-type")
+        .unwrap_or_default()
+        .to_lowercase();
+    let is_audio =
+        content_type.starts_with("audio/") || content_type.starts_with("application/ogg");
+    if !is_icy && !is_audio {
+        return Err(anyhow!("{url} is not an audio file or stream"));
+    }
+    if let Some(length) = response.content_length() {
+        if length > config::audio::MAX_DIRECT_URL_SIZE {
+            return Err(anyhow!(
+                "{url} is larger than the max of {} MB",
+                config::audio::MAX_DIRECT_URL_SIZE / (1024 * 1024)
+            ));
+        }
+    }
+    // files have a known length or can be requested in ranges, while streams are sent until the
+    // client disconnects
+    let is_file = response.content_length().is_some()
+        || get_header(headers, "accept-ranges").is_some_and(|ranges| ranges == "bytes");
+    Ok(UrlInfo {
+        is_live: is_icy || !is_file,
+        name: get_header(headers, "icy-name")
+            .filter(|name| !name.is_empty())
+            .map(str::to_string),
+    })
+}
+
+// e.g. StreamTitle='Artist - Title';StreamUrl='';
+fn parse_stream_title(metadata: &str) -> Option<String> {
+    let title = metadata.split("StreamTitle='").nth(1)?.split("';").next()?;
+    match title.trim() {
+        "" => None,
+        title => Some(title.to_string()),
+    }
+}
+
+// the first metadata block in the stream, which follows metaint bytes of audio and starts with
+// its length in units of 16 bytes. None if buf doesn't contain all of it yet
+fn metadata_block(buf: &[u8], metaint: usize) -> Option<&[u8]> {
+    let length = *buf.get(metaint)? as usize * 16;
+    buf.get(metaint + 1..metaint + 1 + length)
+}
+
+// reads the current title from the first metadata block of the stream. None if the stream has
+// no title
+pub async fn fetch_stream_title(url: &str) -> anyhow::Result<Option<String>> {
+    let mut response = request_metadata(url).await?;
+    // the number of audio bytes between metadata blocks
+    let metaint: usize = match get_header(response.headers(), "icy-metaint") {
+        Some(metaint) => metaint.parse().context("invalid icy-metaint header")?,
+        None => return Ok(None),
+    };
+    if metaint > config::audio::MAX_ICY_METAINT {
+        return Err(anyhow!("icy-metaint of {metaint} is too large"));
+    }
+    let mut buf = vec![];
+    loop {
+        if let Some(block) = metadata_block(&buf, metaint) {
+            return Ok(parse_stream_title(&String::from_utf8_lossy(block)));
+        }
+        let chunk = response
+            .chunk()
+            .await?
+            .context("stream ended before its metadata")?;
+        buf.extend_from_slice(&chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stream_title() {
+        assert_eq!(
+            parse_stream_title("StreamTitle='Artist - Title';StreamUrl='';"),
+            Some("Artist - Title".to_string())
+        );
+        assert_eq!(
+            parse_stream_title("StreamTitle='It's';\0\0"),
+            Some("It's".to_string())
+        );
+        assert_eq!(parse_stream_title("StreamTitle='  ';"), None);
+        assert_eq!(parse_stream_title("StreamUrl='';"), None);
+        assert_eq!(parse_stream_title(""), None);
+    }
+
+    #[test]
+    fn finds_metadata_block() {
+        let mut buf = vec![0xff; 4];
+        // an incomplete stream has no block yet
+        assert_eq!(metadata_block(&buf, 4), None);
+        buf.push(1);
+        buf.extend_from_slice(b"StreamTitle='a';");
+        assert_eq!(metadata_block(&buf, 4), Some(&b"StreamTitle='a';"[..]));
+        // audio after the block is ignored
+        buf.extend_from_slice(&[0xff; 8]);
+        assert_eq!(metadata_block(&buf, 4), Some(&b"StreamTitle='a';"[..]));
+        // a truncated block isn't returned
+        assert_eq!(metadata_block(&buf[..12], 4), None);
+    }
+
+    #[test]
+    fn empty_metadata_block() {
+        let buf = [0xff, 0xff, 0];
+        assert_eq!(metadata_block(&buf, 2), Some(&[][..]));
+    }
+
+    #[test]
+    fn rejects_private_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "192.168.1.1",
+            "169.254.1.1",
+            "::1",
+            "fd00::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        assert!(!is_public("::ffff:127.0.0.1".parse().unwrap()));
+        assert!(is_public("1.1.1.1".parse().unwrap()));
+        assert!(is_public("2606:4700::1111".parse().unwrap()));
+    }
+
+    #[test]
+    fn rejects_private_url_hosts() {
+        for url in [
+            "http://127.0.0.1/a.mp3",
+            "http://169.254.169.254/latest",
+            "http://[::1]:8000/",
+            "http://localhost/",
+            "file:///etc/passwd",
+        ] {
+            assert!(
+                check_public_url(&Url::parse(url).unwrap()).is_err(),
+                "{url}"
+            );
+        }
+        // domains are checked when they are resolved
+        assert!(check_public_url(&Url::parse("https://example.com/a.mp3").unwrap()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn resolver_rejects_private_addresses() {
+        let name: Name = "localhost".parse().unwrap();
+        assert!(PublicResolver.resolve(name).await.is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serenity::prelude::TypeMapKey;
+
+use super::{
+    config,
+    song::{HowToFind, SongMetadata, SongRecord},
+    tags::{read_tags, AudioTags},
+    types::StreamType,
+};
+
+// an audio file in the library, with the tags read from it
+struct LibraryTrack {
+    path: PathBuf,
+    artist: Option<String>,
+    title: Option<String>,
+    duration: Option<u64>,
+    // lowercase text that searches are matched against, including the album and file name
+    search_text: String,
+}
+
+impl LibraryTrack {
+    fn read(path: PathBuf) -> anyhow::Result<Self> {
+        let file = fs::File::open(&path).context("failed to open file")?;
+        let AudioTags {
+            artist,
+            title,
+            album,
+            duration,
+        } = read_tags(Box::new(file), path.extension().and_then(OsStr::to_str))?;
+        // untagged files are still findable by their file name
+        let title = title.or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        });
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        let search_text = [&artist, &title, &album, &file_name]
+            .into_iter()
+            .flatten()
+            .map(|s| s.to_lowercase())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok(Self {
+            path,
+            artist,
+            title,
+            duration,
+            search_text,
+        })
+    }
+
+    fn to_record(&self, stream_type: StreamType) -> SongRecord {
+        let metadata = SongMetadata {
+            artist: self.artist.clone(),
+            title: self.title.clone(),
+            how_to_find: HowToFind::LocalFile(self.path.clone()),
+            duration: self.duration,
+            requester: None,
+            is_live: false,
+        };
+        SongRecord::new(metadata, stream_type)
+    }
+}
+
+// the audio files found in the configured music library directory
+#[derive(Default)]
+pub struct Library {
+    root: Option<PathBuf>,
+    tracks: Vec<LibraryTrack>,
+}
+
+fn has_audio_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|extension| {
+            config::audio::LIBRARY_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+        })
+}
+
+// recursively collects the audio files in the directory. Files and subdirectories that can't be
+// read are skipped. Symlinked directories aren't followed, as they could form a cycle
+fn index_dir(dir: &Path, tracks: &mut Vec<LibraryTrack>) -> anyhow::Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("failed to read directory {dir:?}"))?;
+    for entry in entries {
+        let (path, file_type) = match entry.and_then(|entry| Ok((entry.path(), entry.file_type()?)))
+        {
+            Ok(entry) => entry,
+            Err(why) => {
+                log::warn!("skipping library entry in {:?}: {}", dir, why);
+                continue;
+            }
+        };
+        if file_type.is_dir() {
+            if let Err(why) = index_dir(&path, tracks) {
+                log::warn!("skipping library directory: {}", why);
+            }
+        } else if has_audio_extension(&path) {
+            match LibraryTrack::read(path.clone()) {
+                Ok(track) => tracks.push(track),
+                Err(why) => log::warn!("skipping library file {:?}: {}", path, why),
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Library {
+    // reads the tags of every audio file under root. This is slow, so should not be called from
+    // async code
+    pub fn index(root: Option<PathBuf>) -> anyhow::Result<Self> {
+        let mut tracks = vec![];
+        if let Some(root) = &root {
+            index_dir(root, &mut tracks)?;
+            log::info!(
+                "indexed {} tracks in music library {:?}",
+                tracks.len(),
+                root
+            );
+        }
+        Ok(Self { root, tracks })
+    }
+
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    pub fn num_tracks(&self) -> usize {
+        self.tracks.len()
+    }
+
+    // tracks whose tags or path contain every word of the query
+    pub fn search(&self, query: &str, amount: usize, stream_type: StreamType) -> Vec<SongRecord> {
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        self.tracks
+            .iter()
+            .filter(|track| words.iter().all(|word| track.search_text.contains(word)))
+            .take(amount)
+            .map(|track| track.to_record(stream_type))
+            .collect()
+    }
+}
+
+impl TypeMapKey for Library {
+    type Value = Self;
+}
@@ -8,7 +8,9 @@ use std::{
 };
 
 use crate::{
-    config::audio::MESSAGE_UI_COMPONENT_CHAIN_INTERVAL_MS, util::get_styled_embed, PoiseContext,
+    config::audio::{MESSAGE_UI_COMPONENT_CHAIN_INTERVAL_MS, REMOVE_SONGS_MENU_TIMEOUT},
+    util::get_styled_embed,
+    PoiseContext,
 };
 use anyhow::{anyhow, Context as AContext};
 use futures::StreamExt;
@@ -24,13 +26,14 @@ use serenity::all::{
     CreateInteractionResponseMessage, CreateMessage, CreateModal, CreateSelectMenuKind, InputText,
     ModalInteraction,
 };
-use songbird::tracks::TrackHandle;
 use tokio::{sync::Mutex, time::timeout};
 
 use super::{
     audio_state::AudioState,
     db::Db,
-    types::{QueuePosition, StreamType},
+    permissions::{self, authorize_interaction, is_dj},
+    queue_view::{queue_view_contents, run_queue_view},
+    types::{QueuePosition, SkipOutcome, StreamType},
 };
 
 #[derive(Clone, Copy)]
@@ -65,6 +68,35 @@ fn create_db_buttom_id(db_key: &str) -> String {
     format!("add_songs_from_db_{db_key}")
 }
 
+// discord does not allow select menus with more than 25 options
+const MAX_SELECT_MENU_OPTIONS: usize = 25;
+// discord does not allow select menu labels longer than 100 characters
+const MAX_SELECT_MENU_LABEL_LEN: usize = 100;
+
+fn truncate_label(label: String) -> String {
+    match label.char_indices().nth(MAX_SELECT_MENU_LABEL_LEN) {
+        Some((index, _)) => label[..index].to_string(),
+        None => label,
+    }
+}
+
+// the qualified name of the command whose permission level applies to a button, if any
+fn interaction_command(id: &str) -> Option<&'static str> {
+    match id {
+        "skip" => Some("skip"),
+        "clear" => Some("clear"),
+        "play_pause" => Some("pause_resume"),
+        "loop" => Some("looping"),
+        "previous" => Some("previous"),
+        "add_songs" => Some("play"),
+        "queue" => Some("queue"),
+        "remove_songs" => Some("remove"),
+        id if parse_db_buttom_id(id).is_some() => Some("play"),
+        // the remaining components only change the preferences of the user
+        _ => None,
+    }
+}
+
 fn now_playing_response(query: &str) -> CreateInteractionResponse {
     CreateInteractionResponse::Message(
         CreateInteractionResponseMessage::new()
@@ -81,7 +113,11 @@ impl MessageUiComponent {
         }
     }
 
-    async fn components(context: &Arc<Context>) -> Vec<CreateActionRow> {
+    async fn components(
+        context: &Arc<Context>,
+        audio_state: &Arc<AudioState>,
+    ) -> Vec<CreateActionRow> {
+        let loop_mode = audio_state.get_loop_mode().await;
         let mut all = vec![
             CreateActionRow::Buttons(vec![
                 CreateButton::new("skip")
@@ -103,7 +139,12 @@ impl MessageUiComponent {
                 CreateButton::new("loop")
                     .emoji('🔁')
                     .style(ButtonStyle::Secondary)
-                    .label("Loop")
+                    .label(format!("Loop: {loop_mode}"))
+                    .to_owned(),
+                CreateButton::new("previous")
+                    .emoji('↩')
+                    .style(ButtonStyle::Secondary)
+                    .label("Previous")
                     .to_owned(),
             ]),
             CreateActionRow::SelectMenu(CreateSelectMenu::new(
@@ -157,6 +198,11 @@ impl MessageUiComponent {
                     .style(ButtonStyle::Secondary)
                     .label("Display Queue")
                     .to_owned(),
+                CreateButton::new("remove_songs")
+                    .emoji('✂')
+                    .style(ButtonStyle::Secondary)
+                    .label("Remove Songs")
+                    .to_owned(),
             ]),
         ];
 
@@ -188,7 +234,8 @@ impl MessageUiComponent {
         let m = channel_id
             .send_message(
                 self.context.http.clone(),
-                CreateMessage::new().components(Self::components(&self.context).await),
+                CreateMessage::new()
+                    .components(Self::components(&self.context, &self.audio_state).await),
             )
             .await?;
 
@@ -198,7 +245,10 @@ impl MessageUiComponent {
 
     pub async fn start_with_poise_context(&mut self, ctx: &PoiseContext<'_>) -> anyhow::Result<()> {
         let handle = ctx
-            .send(CreateReply::default().components(Self::components(&self.context).await))
+            .send(
+                CreateReply::default()
+                    .components(Self::components(&self.context, &self.audio_state).await),
+            )
             .await?;
 
         self.init_handler(handle.into_message().await?);
@@ -291,24 +341,63 @@ impl MessageUiComponent {
         audio_state: &Arc<AudioState>,
     ) -> anyhow::Result<()> {
         let id = mci.data.custom_id.as_str();
+        if let Some(command) = interaction_command(id) {
+            if !authorize_interaction(context, mci, command).await? {
+                return Ok(());
+            }
+        }
 
         match id {
             "skip" => {
-                audio_state.send_track_command(TrackHandle::stop).await?;
-                mci.defer(&context.http).await?;
+                let can_bypass = match (&mci.member, mci.guild_id) {
+                    (Some(member), Some(guild_id)) => {
+                        let policy = permissions::get_policy(context, guild_id).await?;
+                        is_dj(context, guild_id, mci.channel_id, member, &policy)
+                    }
+                    _ => false,
+                };
+                match audio_state.vote_skip(mci.user.id, can_bypass).await? {
+                    SkipOutcome::Skipped => mci.defer(&context.http).await?,
+                    SkipOutcome::Voted { votes, required } => {
+                        mci.create_response(
+                            &context.http,
+                            CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new().add_embed(
+                                    get_styled_embed(&format!(
+                                        "<@{}> voted to skip: {votes}/{required}",
+                                        mci.user.id
+                                    ))
+                                    .to_owned(),
+                                ),
+                            ),
+                        )
+                        .await?
+                    }
+                }
             }
             "clear" => {
                 audio_state.clear().await?;
                 mci.defer(&context.http).await?;
             }
             "loop" => {
-                let is_looping = audio_state.change_looping(None).await?;
+                audio_state.change_looping(None).await?;
+                // update the loop button's label in place
+                mci.create_response(
+                    &context.http,
+                    CreateInteractionResponse::UpdateMessage(
+                        CreateInteractionResponseMessage::new()
+                            .components(Self::components(context, audio_state).await),
+                    ),
+                )
+                .await?
+            }
+            "previous" => {
+                let song = audio_state.previous(mci.user.id).await?;
                 mci.create_response(
                     &context.http,
                     CreateInteractionResponse::Message(
-                        CreateInteractionResponseMessage::new().add_embed(
-                            get_styled_embed(&format!("Looping: {is_looping}")).to_owned(),
-                        ),
+                        CreateInteractionResponseMessage::new()
+                            .add_embed(get_styled_embed(&format!("Re-queued: {song}")).to_owned()),
                     ),
                 )
                 .await?
@@ -400,16 +489,95 @@ impl MessageUiComponent {
                 .await?;
             }
             "queue" => {
-                let text = audio_state.get_string().await;
+                let (embed, components) = queue_view_contents(audio_state).await;
                 mci.create_response(
                     &context.http,
                     CreateInteractionResponse::Message(
                         CreateInteractionResponseMessage::new()
-                            .add_embed(get_styled_embed(&text).to_owned()),
+                            .add_embed(embed)
+                            .components(components),
                     ),
                 )
                 .await?;
                 audio_state.display_ui().await?;
+                let m = mci.get_response(&context.http).await?;
+                let context = context.clone();
+                let audio_state = audio_state.clone();
+                // don't block the interaction loop while the queue is being browsed
+                tokio::spawn(async move {
+                    if let Err(why) = run_queue_view(&context, m, &audio_state).await {
+                        log::error!("error in run_queue_view: {}", why);
+                    }
+                });
+            }
+            "remove_songs" => {
+                let songs = audio_state
+                    .get_queue_song_entries(MAX_SELECT_MENU_OPTIONS)
+                    .await;
+                if songs.is_empty() {
+                    mci.create_response(
+                        &context.http,
+                        CreateInteractionResponse::Message(
+                            CreateInteractionResponseMessage::new()
+                                .add_embed(get_styled_embed("Queue is empty").to_owned())
+                                .ephemeral(true),
+                        ),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+                let options = songs
+                    .into_iter()
+                    .enumerate()
+                    // the queue may change before a selection is made, so songs are selected by id
+                    .map(|(i, (id, song))| {
+                        CreateSelectMenuOption::new(
+                            truncate_label(format!("{}. {}", i + 1, song)),
+                            id.to_string(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let num_options = options.len() as u8;
+                mci.create_response(
+                    &context.http,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .components(vec![CreateActionRow::SelectMenu(
+                                CreateSelectMenu::new(
+                                    "remove_songs_selection",
+                                    CreateSelectMenuKind::String { options },
+                                )
+                                .placeholder("Select songs to remove")
+                                .min_values(1)
+                                .max_values(num_options),
+                            )])
+                            .ephemeral(true),
+                    ),
+                )
+                .await?;
+                let m = mci.get_response(&context.http).await?;
+                // DJs may remove any song, everyone else only the songs they requested
+                let requester = match (&mci.member, mci.guild_id) {
+                    (Some(member), Some(guild_id)) => {
+                        let policy = permissions::get_policy(context, guild_id).await?;
+                        match is_dj(context, guild_id, mci.channel_id, member, &policy) {
+                            true => None,
+                            false => Some(mci.user.id),
+                        }
+                    }
+                    _ => Some(mci.user.id),
+                };
+                let context = context.clone();
+                let audio_state = audio_state.clone();
+                // don't block the interaction loop while waiting for the user's selection
+                tokio::spawn(async move {
+                    if let Err(why) =
+                        Self::process_remove_songs_selection(m, &context, &audio_state, requester)
+                            .await
+                    {
+                        log::error!("error in process_remove_songs_selection: {}", why);
+                    }
+                });
             }
             db_key_id if parse_db_buttom_id(db_key_id).is_some() => {
                 let query = {
@@ -436,6 +604,7 @@ impl MessageUiComponent {
                         user_state.queue_position,
                         user_state.should_shuffle,
                         user_state.stream_type,
+                        mci.user.id,
                     )
                     .await?;
                 mci.create_response(context, now_playing_response(&query))
@@ -447,6 +616,45 @@ impl MessageUiComponent {
         Ok(())
     }
 
+    async fn process_remove_songs_selection(
+        m: Message,
+        context: &Arc<Context>,
+        audio_state: &Arc<AudioState>,
+        requester: Option<UserId>,
+    ) -> anyhow::Result<()> {
+        let mci = match m
+            .await_component_interaction(&context.shard)
+            .timeout(REMOVE_SONGS_MENU_TIMEOUT)
+            .await
+        {
+            Some(mci) => mci,
+            None => return Ok(()),
+        };
+        let ids = match &mci.data.kind {
+            ComponentInteractionDataKind::StringSelect { values } => values
+                .iter()
+                .map(|value| value.parse::<u64>())
+                .collect::<Result<Vec<_>, _>>()
+                .context("invalid song id in selection")?,
+            other => return Err(anyhow!("unexpected selection {other:#?}")),
+        };
+        // songs that were played or removed in the meantime are no longer in the queue
+        let text = match audio_state.remove_ids(&ids, requester).await {
+            Ok(removed) => format!("Removed {removed} songs"),
+            Err(why) => format!("Error: {why}"),
+        };
+        mci.create_response(
+            &context.http,
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .add_embed(get_styled_embed(&text).to_owned())
+                    .components(vec![]),
+            ),
+        )
+        .await?;
+        Ok(())
+    }
+
     async fn process_modal_interaction(
         mci: &ModalInteraction,
         context: &Arc<Context>,
@@ -493,6 +701,7 @@ impl MessageUiComponent {
                 user_state.queue_position,
                 user_state.should_shuffle,
                 user_state.stream_type,
+                user_id,
             )
             .await?;
         match db_key.is_empty() {
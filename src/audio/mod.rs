@@ -1,16 +1,30 @@
+pub mod audio_cache;
 pub mod audio_state;
 pub mod commands;
 pub mod config;
 pub mod db;
+pub mod library;
+pub mod session;
+pub mod settings;
 
 mod ffmpeg;
+mod filters;
+mod icy;
 mod message_ui_component;
+mod now_playing_component;
+mod permissions;
+mod queue_policy;
+mod queue_view;
 mod song;
 mod song_loader;
+mod song_picker;
 mod song_queue;
 mod song_searcher;
 mod spotify;
+mod tags;
 mod types;
+mod voice_events;
 mod ytdl;
 
 pub use commands::*;
+pub use voice_events::handle_voice_state_update;
@@ -0,0 +1,194 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    config::audio::{
+        MESSAGE_UI_COMPONENT_CHAIN_INTERVAL_MS, NOW_PLAYING_REFRESH_INTERVAL, SEEK_STEP_SECS,
+        VOLUME_STEP,
+    },
+    util::get_styled_embed,
+};
+use futures::StreamExt;
+use poise::serenity_prelude::{
+    ButtonStyle, ChannelId, Context, CreateActionRow, CreateButton, Message,
+};
+use serenity::all::{
+    ComponentInteraction, CreateInteractionResponse, CreateInteractionResponseMessage,
+    CreateMessage, EditMessage,
+};
+use tokio::time::{timeout, Instant};
+
+use super::{audio_state::AudioState, permissions::authorize_interaction};
+
+// the "now playing" message, with controls that only make sense while the song is playing
+pub struct NowPlayingComponent {
+    should_cleanup: Arc<AtomicBool>,
+    should_refresh: Arc<AtomicBool>,
+    context: Arc<Context>,
+    audio_state: Arc<AudioState>,
+}
+
+impl NowPlayingComponent {
+    pub fn new(audio_state: Arc<AudioState>, context: Arc<Context>) -> Self {
+        Self {
+            should_cleanup: Arc::new(AtomicBool::new(false)),
+            should_refresh: Arc::new(AtomicBool::new(false)),
+            context,
+            audio_state,
+        }
+    }
+
+    // refreshes the message as soon as possible, instead of waiting for the next periodic refresh
+    pub fn request_refresh(&self) {
+        self.should_refresh.store(true, Ordering::Relaxed);
+    }
+
+    fn components() -> Vec<CreateActionRow> {
+        vec![CreateActionRow::Buttons(vec![
+            CreateButton::new("seek_back")
+                .emoji('⏪')
+                .style(ButtonStyle::Secondary)
+                .label(format!("-{SEEK_STEP_SECS}s"))
+                .to_owned(),
+            CreateButton::new("seek_forward")
+                .emoji('⏩')
+                .style(ButtonStyle::Secondary)
+                .label(format!("+{SEEK_STEP_SECS}s"))
+                .to_owned(),
+            CreateButton::new("volume_down")
+                .emoji('🔉')
+                .style(ButtonStyle::Secondary)
+                .label(format!("-{VOLUME_STEP}%"))
+                .to_owned(),
+            CreateButton::new("volume_up")
+                .emoji('🔊')
+                .style(ButtonStyle::Secondary)
+                .label(format!("+{VOLUME_STEP}%"))
+                .to_owned(),
+        ])]
+    }
+
+    pub async fn start_with_channel_id(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
+        let text = self.audio_state.get_now_playing_string().await;
+        let m = channel_id
+            .send_message(
+                self.context.http.clone(),
+                CreateMessage::new()
+                    .add_embed(get_styled_embed(&text))
+                    .components(Self::components()),
+            )
+            .await?;
+
+        self.init_handler(m);
+        Ok(())
+    }
+
+    fn init_handler(&mut self, mut m: Message) {
+        let context = self.context.clone();
+        let should_cleanup = self.should_cleanup.clone();
+        let should_refresh = self.should_refresh.clone();
+        let audio_state = self.audio_state.clone();
+        tokio::spawn(async move {
+            let mut mci_iter = m.await_component_interactions(&context.shard).stream();
+            let mut last_refresh = Instant::now();
+            loop {
+                while let Ok(Some(mci)) = timeout(
+                    Duration::from_millis(MESSAGE_UI_COMPONENT_CHAIN_INTERVAL_MS),
+                    mci_iter.next(),
+                )
+                .await
+                {
+                    if let Err(why) =
+                        Self::process_message_interaction(&mci, &context, &audio_state).await
+                    {
+                        log::error!("error in now_playing interaction_loop: {}", why);
+                    }
+                    // show the effect of the interaction straight away
+                    should_refresh.store(true, Ordering::Relaxed);
+                }
+
+                if should_cleanup.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                if should_refresh.swap(false, Ordering::Relaxed)
+                    || last_refresh.elapsed() >= NOW_PLAYING_REFRESH_INTERVAL
+                {
+                    last_refresh = Instant::now();
+                    let text = audio_state.get_now_playing_string().await;
+                    if let Err(why) = m
+                        .edit(
+                            &context.http,
+                            EditMessage::new().embed(get_styled_embed(&text)),
+                        )
+                        .await
+                    {
+                        log::error!("error in now_playing interaction_loop: {}", why);
+                    }
+                }
+            }
+            // keep the message as a record of what was played, but remove the stale controls
+            if let Err(why) = m
+                .edit(&context.http, EditMessage::new().components(vec![]))
+                .await
+            {
+                log::error!("error in now_playing interaction_loop: {}", why);
+            };
+        });
+    }
+
+    async fn process_message_interaction(
+        mci: &ComponentInteraction,
+        context: &Arc<Context>,
+        audio_state: &Arc<AudioState>,
+    ) -> anyhow::Result<()> {
+        let id = mci.data.custom_id.as_str();
+        let command = match id {
+            "seek_back" | "seek_forward" => "seek",
+            _ => "volume",
+        };
+        if !authorize_interaction(context, mci, command).await? {
+            return Ok(());
+        }
+
+        match id {
+            "seek_back" => {
+                audio_state.seek_by(-SEEK_STEP_SECS).await?;
+                mci.defer(&context.http).await?;
+            }
+            "seek_forward" => {
+                audio_state.seek_by(SEEK_STEP_SECS).await?;
+                mci.defer(&context.http).await?;
+            }
+            "volume_down" | "volume_up" => {
+                let delta = match id {
+                    "volume_down" => -VOLUME_STEP,
+                    _ => VOLUME_STEP,
+                };
+                let volume = audio_state.change_volume_by(delta).await?;
+                mci.create_response(
+                    &context.http,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .add_embed(get_styled_embed(&format!("Volume: {volume}%")).to_owned())
+                            .ephemeral(true),
+                    ),
+                )
+                .await?;
+            }
+            _ => unreachable!(),
+        };
+        Ok(())
+    }
+}
+
+impl Drop for NowPlayingComponent {
+    fn drop(&mut self) {
+        self.should_cleanup.swap(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,254 @@
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::{anyhow, Context as AContext};
+use poise::serenity_prelude::{ChannelId, Context, GuildId, Member, RoleId};
+use serde::{Deserialize, Serialize};
+use serenity::all::{
+    ComponentInteraction, CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+
+use crate::util::get_styled_embed;
+
+use super::settings::SettingsDb;
+
+// the command that configures the policy, which only admins can ever use
+const PERMISSIONS_COMMAND: &str = "permissions";
+
+// commands that only show information, so they can be used from outside the voice channel. By
+// qualified name, like every command in the policy
+const READ_ONLY_COMMANDS: [&str; 3] = ["queue", "history", "filter show"];
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum PermissionLevel {
+    Everyone,
+    Dj,
+    Admin,
+}
+
+impl fmt::Display for PermissionLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PermissionLevel::Everyone => write!(f, "everyone"),
+            PermissionLevel::Dj => write!(f, "DJ"),
+            PermissionLevel::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+// who may use which commands and buttons in a guild
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PermissionPolicy {
+    pub dj_role: Option<RoleId>,
+    // commands without an entry have their default level
+    pub command_levels: BTreeMap<String, PermissionLevel>,
+    // whether only users in the bot's voice channel may control it
+    pub require_same_voice_channel: bool,
+}
+
+// the level of commands that aren't for everyone unless a guild says otherwise, by qualified
+// name. Commands that change how the bot behaves for everyone are for DJs
+const DEFAULT_LEVELS: [(&str, PermissionLevel); 14] = [
+    (PERMISSIONS_COMMAND, PermissionLevel::Admin),
+    // these end the session for everyone
+    ("clear", PermissionLevel::Dj),
+    ("exit", PermissionLevel::Dj),
+    ("volume", PermissionLevel::Dj),
+    ("crossfade", PermissionLevel::Dj),
+    ("idle_timeout", PermissionLevel::Dj),
+    ("vote_skip", PermissionLevel::Dj),
+    ("fair_queue", PermissionLevel::Dj),
+    ("queue_policy", PermissionLevel::Dj),
+    ("loudnorm_targets", PermissionLevel::Dj),
+    ("stream_type", PermissionLevel::Dj),
+    ("filter", PermissionLevel::Dj),
+    ("filter show", PermissionLevel::Everyone),
+    // rescanning reads every file in the library, so shouldn't be spammed
+    ("library rescan", PermissionLevel::Dj),
+];
+
+fn own_default_level(command: &str) -> Option<PermissionLevel> {
+    DEFAULT_LEVELS
+        .iter()
+        .find(|(name, _)| *name == command)
+        .map(|(_, level)| *level)
+}
+
+// e.g. "permissions" and "permissions dj_role"
+pub fn is_permissions_command(command: &str) -> bool {
+    command.split(' ').next() == Some(PERMISSIONS_COMMAND)
+}
+
+impl PermissionPolicy {
+    // commands are identified by their qualified name, e.g. "filter bass". Subcommands without a
+    // level of their own have the level of their parent
+    pub fn level(&self, command: &str) -> PermissionLevel {
+        if is_permissions_command(command) {
+            return PermissionLevel::Admin;
+        }
+        self.command_levels
+            .get(command)
+            .copied()
+            .unwrap_or_else(|| self.inherited_level(command))
+    }
+
+    // the level the command would have without an entry of its own
+    pub fn inherited_level(&self, command: &str) -> PermissionLevel {
+        if let Some(level) = own_default_level(command) {
+            return level;
+        }
+        match command.rsplit_once(' ') {
+            Some((parent, _)) => self.level(parent),
+            None => PermissionLevel::Everyone,
+        }
+    }
+
+    pub fn get_string(&self) -> String {
+        let mut res = format!(
+            "**DJ role:** {}\n**Only users in the voice channel:** {}\n",
+            match self.dj_role {
+                Some(role_id) => format!("<@&{role_id}>"),
+                None => "*none*".to_string(),
+            },
+            self.require_same_voice_channel,
+        );
+        let mut levels: BTreeMap<&str, PermissionLevel> = DEFAULT_LEVELS
+            .iter()
+            .map(|(command, _)| *command)
+            .chain(self.command_levels.keys().map(String::as_str))
+            .filter(|command| !is_permissions_command(command))
+            .map(|command| (command, self.level(command)))
+            .collect();
+        levels.retain(|_, level| *level != PermissionLevel::Everyone);
+        if levels.is_empty() {
+            res += "*every command can be used by everyone*";
+        }
+        for (command, level) in levels {
+            res += &format!("`{command}`: {level}\n");
+        }
+        res
+    }
+}
+
+pub async fn get_policy(context: &Context, guild_id: GuildId) -> anyhow::Result<PermissionPolicy> {
+    let data = context.data.read().await;
+    let settings = data
+        .get::<SettingsDb>()
+        .context("SettingsDb object was not initialized in serenity TypeMap")?
+        .get(guild_id);
+    Ok(settings.permissions)
+}
+
+// whether the member manages the guild, and may bypass every restriction
+pub fn is_elevated(
+    context: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    member: &Member,
+) -> bool {
+    // members of interactions come with their permissions already resolved
+    let permissions = match member.permissions {
+        Some(permissions) => permissions,
+        None => {
+            let guild = match context.cache.guild(guild_id) {
+                Some(guild) => guild,
+                None => return false,
+            };
+            match guild.channels.get(&channel_id) {
+                Some(channel) => guild.user_permissions_in(channel, member),
+                None => return false,
+            }
+        }
+    };
+    permissions.administrator() || permissions.manage_guild()
+}
+
+// whether the member may use DJ commands, and bypass restrictions such as vote skipping
+pub fn is_dj(
+    context: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    member: &Member,
+    policy: &PermissionPolicy,
+) -> bool {
+    let has_dj_role = match policy.dj_role {
+        Some(role_id) => member.roles.contains(&role_id),
+        None => false,
+    };
+    has_dj_role || is_elevated(context, guild_id, channel_id, member)
+}
+
+// returns an error describing why the member may not use the command, if they may not
+pub async fn check_permission(
+    context: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    member: &Member,
+    command: &str,
+) -> anyhow::Result<()> {
+    if is_elevated(context, guild_id, channel_id, member) {
+        return Ok(());
+    }
+    let policy = get_policy(context, guild_id).await?;
+    let allowed = match policy.level(command) {
+        PermissionLevel::Everyone => true,
+        PermissionLevel::Dj => is_dj(context, guild_id, channel_id, member, &policy),
+        PermissionLevel::Admin => false,
+    };
+    if !allowed {
+        return Err(anyhow!(
+            "only {} users may use `{command}`",
+            policy.level(command)
+        ));
+    }
+
+    if policy.require_same_voice_channel && !READ_ONLY_COMMANDS.contains(&command) {
+        let guild = context
+            .cache
+            .guild(guild_id)
+            .context("failed to get guild")?;
+        let get_voice_channel = |user_id| {
+            guild
+                .voice_states
+                .get(&user_id)
+                .and_then(|voice_state| voice_state.channel_id)
+        };
+        // the restriction only applies once the bot is in a voice channel
+        if let Some(bot_channel_id) = get_voice_channel(context.cache.current_user().id) {
+            if get_voice_channel(member.user.id) != Some(bot_channel_id) {
+                return Err(anyhow!(
+                    "only users in <#{bot_channel_id}> may use `{command}`"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// checks the permission of the user behind a component interaction. If they may not use the
+// command, tells them why and returns false
+pub async fn authorize_interaction(
+    context: &Context,
+    mci: &ComponentInteraction,
+    command: &str,
+) -> anyhow::Result<bool> {
+    let (guild_id, member) = match (mci.guild_id, &mci.member) {
+        (Some(guild_id), Some(member)) => (guild_id, member),
+        _ => return Ok(true),
+    };
+    match check_permission(context, guild_id, mci.channel_id, member, command).await {
+        Ok(()) => Ok(true),
+        Err(why) => {
+            mci.create_response(
+                &context.http,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .add_embed(get_styled_embed(&format!("Error: {why}")).to_owned())
+                        .ephemeral(true),
+                ),
+            )
+            .await?;
+            Ok(false)
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::util::format_duration;
+
+use super::{config, song::Song};
+
+// limits on what can be added to a guild's queue. None means unlimited
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueuePolicy {
+    pub max_queue_length: Option<usize>,
+    pub max_songs_per_user: Option<usize>,
+    pub max_track_duration_secs: Option<u64>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Rejection {
+    QueueFull { max: usize },
+    UserQuota { max: usize },
+    TooLong { max: Duration },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::QueueFull { max } => write!(f, "the queue is full (max {max} songs)"),
+            Self::UserQuota { max } => write!(f, "you have too many queued songs (max {max})"),
+            Self::TooLong { max } => write!(f, "longer than the max of {}", format_duration(*max)),
+        }
+    }
+}
+
+impl QueuePolicy {
+    pub fn max_track_duration(&self) -> Option<Duration> {
+        self.max_track_duration_secs.map(Duration::from_secs)
+    }
+
+    // why the song can't be added to a queue of the given length, in which its requester
+    // already has the given number of songs
+    pub fn check(
+        &self,
+        song: &Song,
+        queue_len: usize,
+        requester_songs: usize,
+    ) -> Option<Rejection> {
+        if let Some(max) = self.max_queue_length {
+            if queue_len >= max {
+                return Some(Rejection::QueueFull { max });
+            }
+        }
+        if let (Some(max), Some(_)) = (self.max_songs_per_user, song.requester()) {
+            if requester_songs >= max {
+                return Some(Rejection::UserQuota { max });
+            }
+        }
+        // songs with an unknown duration are checked again once they are loaded
+        if let (Some(max), Some(duration)) = (self.max_track_duration(), song.duration()) {
+            if duration > max {
+                return Some(Rejection::TooLong { max });
+            }
+        }
+        None
+    }
+
+    pub fn get_string(&self) -> String {
+        let or_unlimited = |limit: Option<String>| limit.unwrap_or("unlimited".to_string());
+        format!(
+            "**Max queue length:** {}\n**Max songs per user:** {}\n**Max track duration:** {}",
+            or_unlimited(self.max_queue_length.map(|max| max.to_string())),
+            or_unlimited(self.max_songs_per_user.map(|max| max.to_string())),
+            or_unlimited(self.max_track_duration().map(format_duration)),
+        )
+    }
+}
+
+// describes the songs that were not added, with the reason for each
+pub fn get_rejections_string(rejections: &[(String, Rejection)]) -> String {
+    let mut res = format!("Dropped {} songs:\n", rejections.len());
+    for (song, rejection) in rejections.iter().take(config::audio::MAX_LISTED_REJECTIONS) {
+        res += &format!("- {song}: {rejection}\n");
+    }
+    if rejections.len() > config::audio::MAX_LISTED_REJECTIONS {
+        res += &format!(
+            "*...and {} more*",
+            rejections.len() - config::audio::MAX_LISTED_REJECTIONS
+        );
+    }
+    res
+}
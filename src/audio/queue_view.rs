@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use futures::StreamExt;
+use poise::serenity_prelude::{
+    ButtonStyle, Context, CreateActionRow, CreateButton, CreateEmbed, Message,
+};
+use serenity::all::{CreateInteractionResponse, CreateInteractionResponseMessage, EditMessage};
+
+use crate::{config::audio::QUEUE_VIEW_TIMEOUT, util::get_styled_embed};
+
+use super::audio_state::AudioState;
+
+fn page_buttons(page: usize, num_pages: usize) -> Vec<CreateActionRow> {
+    let is_first = page == 0;
+    let is_last = page + 1 >= num_pages;
+    vec![CreateActionRow::Buttons(vec![
+        CreateButton::new("queue_first")
+            .emoji('⏮')
+            .style(ButtonStyle::Secondary)
+            .disabled(is_first),
+        CreateButton::new("queue_prev")
+            .emoji('◀')
+            .style(ButtonStyle::Secondary)
+            .disabled(is_first),
+        CreateButton::new("queue_page")
+            .style(ButtonStyle::Secondary)
+            .label(format!("Page {}/{}", page + 1, num_pages))
+            .disabled(true),
+        CreateButton::new("queue_next")
+            .emoji('▶')
+            .style(ButtonStyle::Secondary)
+            .disabled(is_last),
+        CreateButton::new("queue_last")
+            .emoji('⏭')
+            .style(ButtonStyle::Secondary)
+            .disabled(is_last),
+    ])]
+}
+
+// the contents of the given page, computed from the live queue. Also returns the page actually
+// shown, since the queue may have shrunk
+async fn page_contents(
+    audio_state: &Arc<AudioState>,
+    page: usize,
+) -> (CreateEmbed, Vec<CreateActionRow>, usize) {
+    let (text, num_pages) = audio_state.get_page_string(page).await;
+    let page = page.min(num_pages - 1);
+    (get_styled_embed(&text), page_buttons(page, num_pages), page)
+}
+
+// the first page of the queue, to be sent before calling run_queue_view
+pub async fn queue_view_contents(
+    audio_state: &Arc<AudioState>,
+) -> (CreateEmbed, Vec<CreateActionRow>) {
+    let (embed, components, _) = page_contents(audio_state, 0).await;
+    (embed, components)
+}
+
+// handles the navigation buttons of a message showing the queue, until it times out
+pub async fn run_queue_view(
+    context: &Context,
+    mut m: Message,
+    audio_state: &Arc<AudioState>,
+) -> anyhow::Result<()> {
+    let mut page = 0;
+    let mut mci_iter = m
+        .await_component_interactions(&context.shard)
+        .timeout(QUEUE_VIEW_TIMEOUT)
+        .stream();
+    while let Some(mci) = mci_iter.next().await {
+        let (_, num_pages) = audio_state.get_page_string(page).await;
+        page = match mci.data.custom_id.as_str() {
+            "queue_first" => 0,
+            "queue_prev" => page.saturating_sub(1),
+            "queue_next" => page + 1,
+            "queue_last" => num_pages - 1,
+            other => {
+                log::error!("run_queue_view: unexpected id {}", other);
+                continue;
+            }
+        };
+        let (embed, components, shown_page) = page_contents(audio_state, page).await;
+        page = shown_page;
+        if let Err(why) = mci
+            .create_response(
+                &context.http,
+                CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new()
+                        .embed(embed)
+                        .components(components),
+                ),
+            )
+            .await
+        {
+            log::error!("error in run_queue_view: {}", why);
+        }
+    }
+
+    m.edit(&context.http, EditMessage::new().components(vec![]))
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,283 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context as AContext};
+use futures::StreamExt;
+use poise::serenity_prelude::{
+    ButtonStyle, ChannelId, Context, CreateActionRow, CreateButton, GuildId, Message,
+};
+use serde::{Deserialize, Serialize};
+use serenity::{
+    all::{ComponentInteraction, CreateMessage, EditMessage},
+    prelude::{TypeMap, TypeMapKey},
+};
+use tokio::{
+    sync::{Mutex, RwLock},
+    time::sleep,
+};
+
+use crate::{
+    config::audio::{SESSION_RESTORE_TIMEOUT, SESSION_SAVE_INTERVAL},
+    util::get_styled_embed,
+};
+
+use super::{
+    audio_cache::AudioCache,
+    audio_state::AudioState,
+    db::{load_or_init_data, save_data},
+    permissions::authorize_interaction,
+    settings::SettingsDb,
+    song::SongRecord,
+    types::{LoopMode, StreamType},
+};
+
+type AudioStates = Arc<Mutex<HashMap<GuildId, Arc<AudioState>>>>;
+
+// everything needed to resume a guild's session after a restart, without any loaded audio
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SavedSession {
+    pub voice_channel_id: ChannelId,
+    pub text_channel_id: ChannelId,
+    pub current_song: Option<SongRecord>,
+    pub queue: Vec<SongRecord>,
+    pub loop_mode: LoopMode,
+    pub stream_type: StreamType,
+}
+
+impl SavedSession {
+    pub fn num_songs(&self) -> usize {
+        self.queue.len() + self.current_song.iter().count()
+    }
+}
+
+type Data = BTreeMap<u64, SavedSession>;
+
+pub struct SessionDb {
+    path: String,
+    data: Data,
+}
+
+impl SessionDb {
+    pub fn new(path: String) -> anyhow::Result<Self> {
+        let data = load_or_init_data(&path, "session db")?;
+        Ok(Self { data, path })
+    }
+
+    pub fn get_all(&self) -> Vec<(GuildId, SavedSession)> {
+        self.data
+            .iter()
+            .map(|(guild_id, session)| (GuildId::new(*guild_id), session.clone()))
+            .collect()
+    }
+
+    // if session is None, the guild has nothing worth restoring
+    pub fn insert(&mut self, guild_id: GuildId, session: Option<SavedSession>) {
+        match session {
+            Some(session) => self.data.insert(guild_id.get(), session),
+            None => self.data.remove(&guild_id.get()),
+        };
+    }
+
+    pub fn remove_and_flush(&mut self, guild_id: GuildId) -> anyhow::Result<()> {
+        if self.data.remove(&guild_id.get()).is_some() {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&self) -> anyhow::Result<()> {
+        save_data(&self.path, "session db", &self.data)
+    }
+}
+
+impl TypeMapKey for SessionDb {
+    type Value = Self;
+}
+
+// saves the sessions of every active guild. Sessions of other guilds are kept, since they may
+// still be waiting to be restored
+pub async fn save_sessions(
+    audio_states: &AudioStates,
+    type_map: &RwLock<TypeMap>,
+) -> anyhow::Result<()> {
+    // the map isn't kept locked while each guild's state is read, so guilds can still join and
+    // leave in the meantime
+    let audio_states: Vec<_> = audio_states
+        .lock()
+        .await
+        .iter()
+        .map(|(guild_id, audio_state)| (*guild_id, audio_state.clone()))
+        .collect();
+    let mut sessions = vec![];
+    for (guild_id, audio_state) in audio_states {
+        sessions.push((guild_id, audio_state.get_saved_session().await));
+    }
+    let mut data = type_map.write().await;
+    let session_db = data
+        .get_mut::<SessionDb>()
+        .context("SessionDb object was not initialized in serenity TypeMap")?;
+    for (guild_id, session) in sessions {
+        session_db.insert(guild_id, session);
+    }
+    session_db.flush()
+}
+
+pub async fn run_session_saver(audio_states: AudioStates, type_map: Arc<RwLock<TypeMap>>) {
+    loop {
+        sleep(SESSION_SAVE_INTERVAL).await;
+        if let Err(why) = save_sessions(&audio_states, &type_map).await {
+            log::error!("error in run_session_saver: {}", why);
+        }
+    }
+}
+
+fn restore_buttons() -> Vec<CreateActionRow> {
+    vec![CreateActionRow::Buttons(vec![
+        CreateButton::new("restore_session")
+            .style(ButtonStyle::Primary)
+            .label("Restore"),
+        CreateButton::new("dismiss_session")
+            .style(ButtonStyle::Secondary)
+            .label("Dismiss"),
+    ])]
+}
+
+// asks every guild with a saved session whether to restore it
+pub async fn offer_restore(context: Arc<Context>, audio_states: AudioStates) {
+    let sessions = {
+        let data = context.data.read().await;
+        match data.get::<SessionDb>() {
+            Some(session_db) => session_db.get_all(),
+            None => {
+                log::error!("SessionDb object was not initialized in serenity TypeMap");
+                return;
+            }
+        }
+    };
+    for (guild_id, session) in sessions {
+        let text = format!(
+            "The bot restarted while playing in <#{}>. Restore the session with {} songs?",
+            session.voice_channel_id,
+            session.num_songs()
+        );
+        let m = match session
+            .text_channel_id
+            .send_message(
+                &context.http,
+                CreateMessage::new()
+                    .add_embed(get_styled_embed(&text))
+                    .components(restore_buttons()),
+            )
+            .await
+        {
+            Ok(m) => m,
+            Err(why) => {
+                log::error!("error in offer_restore: {}", why);
+                continue;
+            }
+        };
+        let context = context.clone();
+        let audio_states = audio_states.clone();
+        tokio::spawn(async move {
+            if let Err(why) =
+                handle_restore_offer(&context, &audio_states, m, guild_id, session).await
+            {
+                log::error!("error in offer_restore: {}", why);
+            }
+        });
+    }
+}
+
+async fn handle_restore_offer(
+    context: &Arc<Context>,
+    audio_states: &AudioStates,
+    mut m: Message,
+    guild_id: GuildId,
+    session: SavedSession,
+) -> anyhow::Result<()> {
+    let mut mci_iter = m
+        .await_component_interactions(&context.shard)
+        .timeout(SESSION_RESTORE_TIMEOUT)
+        .stream();
+    let mut text = "Saved session expired".to_string();
+    while let Some(mci) = mci_iter.next().await {
+        // dismissing discards the queue just like restoring replaces it, so both need the same
+        // permission
+        if !authorize_interaction(context, &mci, "play").await? {
+            continue;
+        }
+        let is_restore = mci.data.custom_id == "restore_session";
+        // joining the voice channel may take longer than discord waits for a response
+        mci.defer(&context.http).await?;
+        text = match is_restore {
+            true => match restore(context, audio_states, &mci, guild_id, session).await {
+                Ok(()) => "Session restored".to_string(),
+                Err(why) => format!("Error: failed to restore session: {why}"),
+            },
+            false => "Saved session dismissed".to_string(),
+        };
+        break;
+    }
+
+    // the session is either restored, in which case it will be saved again, or not wanted anymore
+    let is_active = audio_states.lock().await.contains_key(&guild_id);
+    if !is_active {
+        let mut data = context.data.write().await;
+        data.get_mut::<SessionDb>()
+            .context("SessionDb object was not initialized in serenity TypeMap")?
+            .remove_and_flush(guild_id)?;
+    }
+    m.edit(
+        &context.http,
+        EditMessage::new()
+            .embed(get_styled_embed(&text))
+            .components(vec![]),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn restore(
+    context: &Arc<Context>,
+    audio_states: &AudioStates,
+    mci: &ComponentInteraction,
+    guild_id: GuildId,
+    session: SavedSession,
+) -> anyhow::Result<()> {
+    if audio_states.lock().await.contains_key(&guild_id) {
+        return Err(anyhow!("the bot is already active in this guild"));
+    }
+    let manager = songbird::get(context)
+        .await
+        .context("songbird was not initialized")?;
+    let handler = manager.join(guild_id, session.voice_channel_id).await?;
+    let (settings, cache) = {
+        let data = context.data.read().await;
+        let settings = data
+            .get::<SettingsDb>()
+            .context("SettingsDb object was not initialized in serenity TypeMap")?
+            .get(guild_id);
+        let cache = data
+            .get::<AudioCache>()
+            .context("AudioCache object was not initialized in serenity TypeMap")?
+            .clone();
+        (settings, cache)
+    };
+    let audio_state = AudioState::new(
+        handler,
+        guild_id,
+        settings,
+        cache,
+        context.clone(),
+        mci.channel_id,
+    );
+    audio_states
+        .lock()
+        .await
+        .insert(guild_id, audio_state.clone());
+    audio_state.restore_session(session).await?;
+    audio_state.display_ui().await?;
+    Ok(())
+}
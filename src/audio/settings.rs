@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use poise::serenity_prelude::GuildId;
+use serde::{Deserialize, Serialize};
+use serenity::prelude::TypeMapKey;
+
+use super::{
+    db::{load_or_init_data, save_data},
+    filters::FilterChain,
+    permissions::PermissionPolicy,
+    queue_policy::QueuePolicy,
+    types::LoudnormTargets,
+};
+
+// per-guild settings that should survive reconnects and restarts
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+    // in percent, 100 plays audio at its original volume
+    pub volume: u32,
+    // 0 disables crossfading
+    pub crossfade_secs: u64,
+    // how long the bot stays in a voice channel without listeners before disconnecting
+    pub idle_timeout_secs: u64,
+    // whether skipping requires votes from a fraction of the listeners
+    pub vote_skip: bool,
+    pub vote_skip_ratio: f64,
+    // whether the queue takes songs from each requester in turn
+    pub fair_queue: bool,
+    pub permissions: PermissionPolicy,
+    pub queue_policy: QueuePolicy,
+    // audio effects applied while playing
+    pub filters: FilterChain,
+    pub loudnorm_targets: LoudnormTargets,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            volume: 100,
+            crossfade_secs: 0,
+            idle_timeout_secs: 300,
+            vote_skip: false,
+            vote_skip_ratio: 0.5,
+            fair_queue: false,
+            permissions: PermissionPolicy::default(),
+            queue_policy: QueuePolicy::default(),
+            filters: FilterChain::default(),
+            loudnorm_targets: LoudnormTargets::default(),
+        }
+    }
+}
+
+type Data = BTreeMap<u64, GuildSettings>;
+
+pub struct SettingsDb {
+    path: String,
+    data: Data,
+}
+
+impl SettingsDb {
+    pub fn new(path: String) -> anyhow::Result<Self> {
+        let data = load_or_init_data(&path, "settings db")?;
+        Ok(Self { data, path })
+    }
+
+    pub fn get(&self, guild_id: GuildId) -> GuildSettings {
+        self.data.get(&guild_id.get()).cloned().unwrap_or_default()
+    }
+
+    pub fn insert_and_flush(
+        &mut self,
+        guild_id: GuildId,
+        settings: GuildSettings,
+    ) -> anyhow::Result<()> {
+        self.data.insert(guild_id.get(), settings);
+        save_data(&self.path, "settings db", &self.data)
+    }
+
+    // applies the change to the stored settings of the guild, and returns the updated settings
+    pub fn update_and_flush<F: FnOnce(&mut GuildSettings)>(
+        &mut self,
+        guild_id: GuildId,
+        f: F,
+    ) -> anyhow::Result<GuildSettings> {
+        let mut settings = self.get(guild_id);
+        f(&mut settings);
+        self.insert_and_flush(guild_id, settings.clone())?;
+        Ok(settings)
+    }
+}
+
+impl TypeMapKey for SettingsDb {
+    type Value = Self;
+}
@@ -1,38 +1,233 @@
-use super::types::{AudioReaderConfig, SongLoaderWork, StreamType};
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use poise::serenity_prelude::UserId;
+use serde::{Deserialize, Serialize};
+
+use crate::util::format_duration;
+
+use super::{
+    config,
+    types::{AudioReaderConfig, AudioSource, LoadFailure, SongLoaderWork, StreamType},
+};
 
 pub enum SongPlayableState {
-    Waiting { work: SongLoaderWork },
-    Ready { config: AudioReaderConfig },
+    // waiting for the loader to get to it
+    Waiting {
+        work: SongLoaderWork,
+    },
+    // being loaded by the loader
+    Loading {
+        work: SongLoaderWork,
+    },
+    Ready {
+        config: AudioReaderConfig,
+        loaded_at: Instant,
+    },
+    Failed {
+        reason: LoadFailure,
+    },
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum HowToFind {
     SearchQuery(String),
     YoutubeTrackUrl(String),
+    // a file in the music library
+    LocalFile(PathBuf),
+    // the url of an audio file uploaded to discord
+    Attachment(String),
+    // the url of an audio file or internet radio stream
+    DirectUrl(String),
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SongMetadata {
     pub artist: Option<String>,
     pub title: Option<String>,
     pub how_to_find: HowToFind,
     pub duration: Option<u64>,
+    // the user who added the song to the queue
+    pub requester: Option<UserId>,
+    // e.g. internet radio, which has no duration
+    #[serde(default)]
+    pub is_live: bool,
 }
 
+// songs are told apart by id, since their position in the queue changes as it is edited
+static NEXT_SONG_ID: AtomicU64 = AtomicU64::new(0);
+
 pub struct Song {
     pub state: SongPlayableState,
+    id: u64,
+    metadata: SongMetadata,
+    stream_type: StreamType,
+}
+
+// everything needed to find and load a song again, without any loaded audio
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SongRecord {
     metadata: SongMetadata,
+    stream_type: StreamType,
+}
+
+impl SongRecord {
+    pub fn new(metadata: SongMetadata, stream_type: StreamType) -> Self {
+        Self {
+            metadata,
+            stream_type,
+        }
+    }
+    // the song is attributed to whoever queues it again
+    pub fn to_song(&self, requester: UserId) -> Song {
+        let metadata = SongMetadata {
+            requester: Some(requester),
+            ..self.metadata.clone()
+        };
+        Song::new_load(metadata, self.stream_type)
+    }
+    // unlike to_song, the song stays attributed to its original requester
+    pub fn into_song(self) -> Song {
+        Song::new_load(self.metadata, self.stream_type)
+    }
+    // attachment urls are signed and expire, so can't be loaded again after a restart
+    pub fn is_restorable(&self) -> bool {
+        !matches!(self.metadata.how_to_find, HowToFind::Attachment(_))
+    }
+    pub fn get_string(&self) -> String {
+        get_metadata_string(&self.metadata)
+    }
+}
+
+fn get_metadata_string(metadata: &SongMetadata) -> String {
+    let artist = match &metadata.artist {
+        Some(artist) => artist,
+        None => "unknown",
+    };
+    let title = match &metadata.title {
+        Some(title) => title,
+        None => "unknown",
+    };
+    let duration = match &metadata.duration {
+        Some(duration) => format_duration(Duration::from_secs(*duration)),
+        None if metadata.is_live => "live".to_string(),
+        None => "unknown duration".to_string(),
+    };
+    format!("{} by {} | {}", title, artist, &duration)
 }
 
 impl Song {
     pub fn new_load(metadata: SongMetadata, stream_type: StreamType) -> Self {
-        let query = match metadata.how_to_find.clone() {
-            HowToFind::YoutubeTrackUrl(url) => url,
-            HowToFind::SearchQuery(query) => format!("ytsearch:{} official music", query),
+        let source = match metadata.how_to_find.clone() {
+            HowToFind::YoutubeTrackUrl(url) => AudioSource::Ytdl(url),
+            HowToFind::SearchQuery(query) => {
+                AudioSource::Ytdl(format!("ytsearch:{} official music", query))
+            }
+            HowToFind::LocalFile(path) => AudioSource::LocalFile(path),
+            HowToFind::Attachment(url) | HowToFind::DirectUrl(url) => AudioSource::Url(url),
+        };
+        // live streams never end, so can't be downloaded up front to be loudnormed
+        let stream_type = match metadata.is_live {
+            true => StreamType::Online,
+            false => stream_type,
         };
 
-        let work = SongLoaderWork { query, stream_type };
+        let work = SongLoaderWork {
+            source,
+            stream_type,
+        };
         let state = SongPlayableState::Waiting { work };
-        Song { state, metadata }
+        Song {
+            state,
+            id: NEXT_SONG_ID.fetch_add(1, Ordering::Relaxed),
+            metadata,
+            stream_type,
+        }
+    }
+
+    // returns this song ready to be queued again, keeping the loaded audio if it can be played again
+    pub fn into_requeued(self) -> Song {
+        let reusable = match &self.state {
+            SongPlayableState::Ready {
+                config: AudioReaderConfig::Online { .. },
+                loaded_at,
+            } => loaded_at.elapsed() < config::audio::ONLINE_SOURCE_URL_TTL,
+            // normalized audio is kept in memory, and reloading it from the disk cache is cheap
+            SongPlayableState::Ready {
+                config: AudioReaderConfig::Loudnorm { .. },
+                ..
+            } => false,
+            SongPlayableState::Ready {
+                config: AudioReaderConfig::LocalFile { .. } | AudioReaderConfig::DirectUrl { .. },
+                ..
+            } => true,
+            // the loader only knows about songs that are in the queue, so it has to start over
+            SongPlayableState::Loading { .. } | SongPlayableState::Failed { .. } => false,
+            SongPlayableState::Waiting { .. } => true,
+        };
+        match reusable {
+            true => self,
+            false => Song::new_load(self.metadata, self.stream_type),
+        }
+    }
+
+    // the url to read stream titles from, if this is a live stream
+    pub fn live_url(&self) -> Option<&str> {
+        match &self.metadata.how_to_find {
+            HowToFind::DirectUrl(url) if self.metadata.is_live => Some(url),
+            _ => None,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn requester(&self) -> Option<UserId> {
+        self.metadata.requester
+    }
+
+    pub fn load_failure(&self) -> Option<&LoadFailure> {
+        match &self.state {
+            SongPlayableState::Failed { reason } => Some(reason),
+            _ => None,
+        }
+    }
+
+    // whether the loader is done with this song, successfully or not
+    pub fn is_loaded(&self) -> bool {
+        matches!(
+            self.state,
+            SongPlayableState::Ready { .. } | SongPlayableState::Failed { .. }
+        )
+    }
+
+    pub fn status_marker(&self) -> &'static str {
+        match self.state {
+            SongPlayableState::Waiting { .. } => "⏳",
+            SongPlayableState::Loading { .. } => "🔄",
+            SongPlayableState::Ready { .. } => "✅",
+            SongPlayableState::Failed { .. } => "⚠️",
+        }
+    }
+
+    // e.g. songs found by a search query only have a known duration once they are loaded
+    pub fn set_duration_if_unknown(&mut self, duration: Duration) {
+        self.metadata.duration.get_or_insert(duration.as_secs());
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.metadata.duration.map(Duration::from_secs)
+    }
+
+    pub fn record(&self) -> SongRecord {
+        SongRecord {
+            metadata: self.metadata.clone(),
+            stream_type: self.stream_type,
+        }
     }
 
     pub fn get_buf_config(&self) -> Option<AudioReaderConfig> {
@@ -46,28 +241,13 @@ impl Song {
             //     }
             // },
             // todo: potentially unnecessary clone
-            SongPlayableState::Ready { config } => Some(config.clone()),
-            SongPlayableState::Waiting { .. } => None,
+            SongPlayableState::Ready { config, .. } => Some(config.clone()),
+            SongPlayableState::Waiting { .. }
+            | SongPlayableState::Loading { .. }
+            | SongPlayableState::Failed { .. } => None,
         }
     }
     pub async fn get_string(&self) -> String {
-        let metadata = &self.metadata;
-        let artist = match &metadata.artist {
-            Some(artist) => artist,
-            None => "unknown",
-        };
-        let title = match &metadata.title {
-            Some(title) => title,
-            None => "unknown",
-        };
-        let duration = match &metadata.duration {
-            Some(duration) => {
-                let mins = duration / 60;
-                let secs = duration - mins * 60;
-                format!("{}:{:0>2}", mins, secs)
-            }
-            None => "unknown duration".to_string(),
-        };
-        format!("{} by {} | {}", title, artist, &duration)
+        get_metadata_string(&self.metadata)
     }
 }
@@ -1,83 +1,255 @@
-use std::{collections::VecDeque, sync::Arc};
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    env,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
 
-use crate::audio::types::AudioReaderConfig;
+use crate::audio::types::{AudioReaderConfig, LoadFailure, LoudnormTargets, SongLoaderWork};
 
 use super::{
+    audio_cache::AudioCache,
     config,
     ffmpeg::get_audio_reader_config,
-    song::{Song, SongPlayableState},
+    queue_policy::QueuePolicy,
+    song::{Song, SongPlayableState, SongRecord},
 };
 use tokio::{
-    sync::Mutex,
-    task::JoinHandle,
-    time::{sleep_until, Instant},
+    sync::{mpsc::UnboundedSender, Mutex, Notify},
+    task::{AbortHandle, JoinHandle, JoinSet},
 };
 
+// the loaded audio and its duration, if known
+type LoadResult = Result<(AudioReaderConfig, Option<Duration>), LoadFailure>;
+
+// how many songs may load at the same time, which is limited by the bot's cpu and bandwidth
+static CONCURRENCY: LazyLock<usize> = LazyLock::new(|| {
+    let default = config::audio::DEFAULT_SONG_LOADER_CONCURRENCY;
+    match env::var(config::env::SONG_LOADER_CONCURRENCY) {
+        Ok(value) => match value.parse() {
+            Ok(concurrency) if concurrency > 0 => concurrency,
+            _ => {
+                log::warn!(
+                    "invalid {}: {value}, using {default}",
+                    config::env::SONG_LOADER_CONCURRENCY
+                );
+                default
+            }
+        },
+        Err(_) => default,
+    }
+});
+
 pub struct SongLoader {
     job_handle: JoinHandle<()>,
 }
 
 impl SongLoader {
-    async fn loader_loop(songs: Arc<Mutex<VecDeque<Song>>>) {
-        loop {
-            sleep_until(
-                Instant::now()
-                    .checked_add(config::audio::SONG_LOADER_POLL_INTERVAL)
-                    .unwrap(),
+    async fn load_audio_reader_config(
+        work: SongLoaderWork,
+        max_duration: Option<Duration>,
+        targets: LoudnormTargets,
+        cache: Arc<AudioCache>,
+    ) -> (SongLoaderWork, LoadResult) {
+        let mut reason = LoadFailure::Timeout;
+        for _ in 0..config::audio::GET_AUDIO_READER_NUM_RETRIES {
+            let source = get_audio_reader_config(
+                &work.source,
+                work.stream_type,
+                max_duration,
+                targets,
+                &cache,
             )
             .await;
-            let work = {
-                let songs = songs.lock().await;
-                songs.iter().find_map(|song| match &song.state {
-                    SongPlayableState::Ready { .. } => None,
-                    SongPlayableState::Waiting { work } => Some(work.clone()),
-                })
+            match source {
+                Ok(source) => return (work, Ok(source)),
+                Err(err) if !err.is_retryable() => return (work, Err(err)),
+                Err(err) => {
+                    log::error!("Error loading audio reader config {}", err);
+                    reason = err;
+                    continue;
+                }
             };
-            if let Some(work) = work {
-                let load_audio_reader_config = async || {
-                    for _ in 0..config::audio::GET_AUDIO_READER_NUM_RETRIES {
-                        let source = get_audio_reader_config(&work.query, work.stream_type).await;
-                        match source {
-                            Ok(source) => return source,
-                            Err(err) => {
-                                log::error!("Error loading audio reader config {}", err);
-                                // self.play_next_song();
-                                continue;
-                            }
-                        };
-                    }
-                    println!(
-                        "failed to load audio after {} retries, skipping",
-                        config::audio::GET_AUDIO_READER_NUM_RETRIES
-                    );
-                    AudioReaderConfig::Error
+        }
+        log::error!(
+            "failed to load audio after {} retries: {}",
+            config::audio::GET_AUDIO_READER_NUM_RETRIES,
+            reason
+        );
+        (work, Err(reason))
+    }
+
+    // the works that should currently be loading: the first waiting songs within the load-ahead
+    // window, closest to the front of the queue first
+    fn prioritized_works(songs: &VecDeque<Song>) -> Vec<SongLoaderWork> {
+        let mut works: Vec<SongLoaderWork> = vec![];
+        for song in songs.iter().take(config::audio::SONG_LOADER_LOAD_AHEAD) {
+            if let SongPlayableState::Waiting { work } | SongPlayableState::Loading { work } =
+                &song.state
+            {
+                // the same song may be queued multiple times, but only needs to be loaded once
+                if !works.contains(work) {
+                    works.push(work.clone());
+                }
+            }
+            if works.len() == *CONCURRENCY {
+                break;
+            }
+        }
+        works
+    }
+
+    // songs are loading exactly when their work is in flight
+    fn update_loading_states(
+        songs: &mut VecDeque<Song>,
+        in_flight: &HashMap<SongLoaderWork, AbortHandle>,
+    ) {
+        for song in songs.iter_mut() {
+            let (work, is_loading) = match &song.state {
+                SongPlayableState::Waiting { work } => (work, false),
+                SongPlayableState::Loading { work } => (work, true),
+                _ => continue,
+            };
+            if in_flight.contains_key(work) != is_loading {
+                let work = work.clone();
+                song.state = match is_loading {
+                    true => SongPlayableState::Waiting { work },
+                    false => SongPlayableState::Loading { work },
                 };
-                let config = load_audio_reader_config().await;
+            }
+        }
+    }
+
+    async fn loader_loop(
+        songs: Arc<Mutex<VecDeque<Song>>>,
+        wakeup: Arc<Notify>,
+        song_ready: Arc<Notify>,
+        policy: Arc<Mutex<QueuePolicy>>,
+        loudnorm_targets: Arc<Mutex<LoudnormTargets>>,
+        cache: Arc<AudioCache>,
+        failures: UnboundedSender<(SongRecord, LoadFailure)>,
+    ) {
+        let mut tasks = JoinSet::new();
+        let mut in_flight: HashMap<SongLoaderWork, AbortHandle> = HashMap::new();
+        loop {
+            {
+                let max_duration = policy.lock().await.max_track_duration();
+                let targets = *loudnorm_targets.lock().await;
                 let mut songs = songs.lock().await;
-                songs.iter_mut().for_each(|song| match &song.state {
-                    SongPlayableState::Ready { .. } => (),
-                    SongPlayableState::Waiting { work: song_work } => {
-                        if work.eq(song_work) {
-                            song.state = SongPlayableState::Ready {
-                                // todo: clone one more time than necessary
-                                config: config.clone(),
+                let works = Self::prioritized_works(&songs);
+                // the queue may have been reordered or cleared since these were started, so make
+                // room for songs that are now closer to the front
+                in_flight.retain(|work, handle| {
+                    let keep = works.contains(work);
+                    if !keep {
+                        handle.abort();
+                    }
+                    keep
+                });
+                for work in works {
+                    if let Entry::Vacant(entry) = in_flight.entry(work) {
+                        let work = entry.key().clone();
+                        entry.insert(tasks.spawn(Self::load_audio_reader_config(
+                            work,
+                            max_duration,
+                            targets,
+                            cache.clone(),
+                        )));
+                    }
+                }
+                Self::update_loading_states(&mut songs, &in_flight);
+            }
+
+            tokio::select! {
+                // songs were added or reordered
+                _ = wakeup.notified() => {}
+                Some(res) = tasks.join_next_with_id(), if !tasks.is_empty() => {
+                    let (work, config) = match res {
+                        Ok((_, res)) => res,
+                        Err(err) => {
+                            if !err.is_cancelled() {
+                                log::error!("Error in SongLoader::loader_loop: {}", err);
+                            }
+                            in_flight.retain(|_, handle| handle.id() != err.id());
+                            continue;
+                        }
+                    };
+                    in_flight.remove(&work);
+                    let mut songs = songs.lock().await;
+                    let mut failed = vec![];
+                    songs.iter_mut().for_each(|song| match &song.state {
+                        SongPlayableState::Ready { .. } | SongPlayableState::Failed { .. } => (),
+                        SongPlayableState::Waiting { work: song_work }
+                        | SongPlayableState::Loading { work: song_work } => {
+                            if work.eq(song_work) {
+                                song.state = match &config {
+                                    // todo: clone one more time than necessary
+                                    Ok((config, duration)) => {
+                                        if let Some(duration) = duration {
+                                            song.set_duration_if_unknown(*duration);
+                                        }
+                                        SongPlayableState::Ready {
+                                            config: config.clone(),
+                                            loaded_at: std::time::Instant::now(),
+                                        }
+                                    }
+                                    Err(reason) => {
+                                        failed.push(song.record());
+                                        SongPlayableState::Failed {
+                                            reason: reason.clone(),
+                                        }
+                                    }
+                                }
                             }
                         }
+                    });
+                    if let Err(reason) = &config {
+                        for record in failed {
+                            let _ = failures.send((record, reason.clone()));
+                        }
+                        // songs that can never load are dropped straight away, freeing their
+                        // place in the queue
+                        if !reason.is_retryable() {
+                            songs.retain(|song| {
+                                song.load_failure().is_none_or(LoadFailure::is_retryable)
+                            });
+                        }
                     }
-                });
+                    song_ready.notify_one();
+                }
             }
         }
     }
 
     pub async fn cleanup(&mut self) -> anyhow::Result<()> {
+        // aborting the loop drops its JoinSet, which aborts any loads in progress
         self.job_handle.abort();
         Ok(())
     }
 
-    pub fn start_new(songs: Arc<Mutex<VecDeque<Song>>>) -> Self {
+    pub fn start_new(
+        songs: Arc<Mutex<VecDeque<Song>>>,
+        wakeup: Arc<Notify>,
+        song_ready: Arc<Notify>,
+        policy: Arc<Mutex<QueuePolicy>>,
+        loudnorm_targets: Arc<Mutex<LoudnormTargets>>,
+        cache: Arc<AudioCache>,
+        failures: UnboundedSender<(SongRecord, LoadFailure)>,
+    ) -> Self {
         let job_handle = tokio::spawn({
             let songs = songs.clone();
-            async move { Self::loader_loop(songs).await }
+            async move {
+                Self::loader_loop(
+                    songs,
+                    wakeup,
+                    song_ready,
+                    policy,
+                    loudnorm_targets,
+                    cache,
+                    failures,
+                )
+                .await
+            }
         });
         Self { job_handle }
     }
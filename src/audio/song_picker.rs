@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use futures::StreamExt;
+use poise::{
+    serenity_prelude::{ButtonStyle, CacheHttp, CreateActionRow, CreateButton},
+    CreateReply,
+};
+use serenity::all::{CreateInteractionResponse, CreateInteractionResponseMessage, EditMessage};
+
+use crate::{
+    config::audio::SONG_PICKER_TIMEOUT,
+    util::{get_styled_embed, send_embed},
+    PoiseContext,
+};
+
+use super::{audio_state::AudioState, permissions::authorize_interaction, song::SongRecord};
+
+// discord does not allow more than 5 buttons in a single action row
+const BUTTONS_PER_ROW: usize = 5;
+
+fn create_pick_button_id(index: usize) -> String {
+    format!("pick_song_{index}")
+}
+
+fn parse_pick_button_id(id: &str) -> Option<usize> {
+    id.strip_prefix("pick_song_")?.parse().ok()
+}
+
+fn pick_response(text: &str) -> CreateInteractionResponse {
+    CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .add_embed(get_styled_embed(text).to_owned())
+            .ephemeral(true),
+    )
+}
+
+// replies with a numbered list of songs, with one button per song that adds it to the queue
+pub async fn show_song_picker(
+    ctx: &PoiseContext<'_>,
+    audio_state: &Arc<AudioState>,
+    title: &str,
+    records: Vec<SongRecord>,
+) -> anyhow::Result<()> {
+    if records.is_empty() {
+        send_embed(
+            ctx.http(),
+            ctx.channel_id(),
+            &format!("**{title}**\n*empty*"),
+        )
+        .await?;
+        return Ok(());
+    }
+    let mut text = format!("**{title}**\n");
+    for (i, record) in records.iter().enumerate() {
+        text += &format!("{}. {}\n", i + 1, record.get_string());
+    }
+    let indices: Vec<usize> = (0..records.len()).collect();
+    let rows = indices
+        .chunks(BUTTONS_PER_ROW)
+        .map(|chunk| {
+            CreateActionRow::Buttons(
+                chunk
+                    .iter()
+                    .map(|i| {
+                        CreateButton::new(create_pick_button_id(*i))
+                            .style(ButtonStyle::Secondary)
+                            .label((i + 1).to_string())
+                    })
+                    .collect(),
+            )
+        })
+        .collect();
+    let handle = ctx
+        .send(
+            CreateReply::default()
+                .embed(get_styled_embed(&text))
+                .components(rows),
+        )
+        .await?;
+    let mut m = handle.into_message().await?;
+
+    let mut mci_iter = m
+        .await_component_interactions(&ctx.serenity_context().shard)
+        .timeout(SONG_PICKER_TIMEOUT)
+        .stream();
+    while let Some(mci) = mci_iter.next().await {
+        let record = match parse_pick_button_id(&mci.data.custom_id).and_then(|i| records.get(i)) {
+            Some(record) => record,
+            None => {
+                log::error!("show_song_picker: unexpected id {}", mci.data.custom_id);
+                continue;
+            }
+        };
+        match authorize_interaction(ctx.serenity_context(), &mci, "play").await {
+            Ok(true) => (),
+            Ok(false) => continue,
+            Err(why) => {
+                log::error!("error in show_song_picker: {}", why);
+                continue;
+            }
+        }
+        let text = match audio_state.requeue(record, mci.user.id).await {
+            Ok(()) => format!("Queued: {}", record.get_string()),
+            Err(why) => format!("Error: {why}"),
+        };
+        if let Err(why) = mci.create_response(ctx.http(), pick_response(&text)).await {
+            log::error!("error in show_song_picker: {}", why);
+        }
+    }
+
+    m.edit(ctx.http(), EditMessage::new().components(vec![]))
+        .await?;
+    Ok(())
+}
@@ -1,25 +1,227 @@
-use super::{song::Song, song_loader::SongLoader, types::QueuePosition};
+use crate::util::format_duration;
+
+use super::{
+    audio_cache::AudioCache,
+    queue_policy::{QueuePolicy, Rejection},
+    song::{Song, SongRecord},
+    song_loader::SongLoader,
+    types::{LoadFailure, LoudnormTargets, QueuePosition},
+};
 use anyhow::anyhow;
+use poise::serenity_prelude::UserId;
 use rand::seq::SliceRandom;
-use std::{cmp::min, collections::VecDeque, sync::Arc};
-use tokio::sync::Mutex;
+use std::{
+    cmp::min,
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{mpsc::UnboundedSender, Mutex, Notify};
 pub struct SongQueue {
     loader: Arc<Mutex<SongLoader>>,
     queue: Arc<Mutex<VecDeque<Song>>>,
+    // wakes up the loader when songs may need loading
+    loader_wakeup: Arc<Notify>,
+    // wakes up the player when the next song may have become ready
+    player_wakeup: Arc<Notify>,
+    // whether songs are interleaved by requester, so that everyone gets a turn
+    fair: AtomicBool,
+    // shared with the loader, which checks the duration of songs once it is known
+    policy: Arc<Mutex<QueuePolicy>>,
+    // shared with the loader, which normalizes songs as they are loaded
+    loudnorm_targets: Arc<Mutex<LoudnormTargets>>,
+}
+
+// takes songs in turn from each requester, keeping the order of each requester's own songs.
+// requesters take turns in the order they first appear
+fn interleave(songs: impl IntoIterator<Item = Song>) -> VecDeque<Song> {
+    let mut sub_queues: Vec<(Option<UserId>, VecDeque<Song>)> = vec![];
+    for song in songs {
+        match sub_queues
+            .iter_mut()
+            .find(|(requester, _)| *requester == song.requester())
+        {
+            Some((_, sub_queue)) => sub_queue.push_back(song),
+            None => sub_queues.push((song.requester(), VecDeque::from([song]))),
+        }
+    }
+    let mut res = VecDeque::new();
+    while !sub_queues.is_empty() {
+        for (_, sub_queue) in sub_queues.iter_mut() {
+            res.extend(sub_queue.pop_front());
+        }
+        sub_queues.retain(|(_, sub_queue)| !sub_queue.is_empty());
+    }
+    res
+}
+
+// inserts the song at the back of its requester's turns, without moving any other song. The
+// round of a song is the number of songs its requester has before it, and the song goes after the
+// last song of the round it belongs to
+fn insert_fair(queue: &mut VecDeque<Song>, song: Song) {
+    let requester = song.requester();
+    let mut counts: HashMap<Option<UserId>, usize> = HashMap::new();
+    let rounds: Vec<usize> = queue
+        .iter()
+        .map(|song| {
+            let count = counts.entry(song.requester()).or_default();
+            *count += 1;
+            *count - 1
+        })
+        .collect();
+    let round = counts.get(&requester).copied().unwrap_or_default();
+    // the requester's own songs keep their order
+    let after = queue
+        .iter()
+        .rposition(|song| song.requester() == requester)
+        .map_or(0, |index| index + 1);
+    let index = (after..queue.len())
+        .find(|&index| rounds[index] > round)
+        .unwrap_or(queue.len());
+    queue.insert(index, song);
+}
+
+// puts the songs in front of their requester's other songs. The requester's turns stay where they
+// are, so the songs that no longer fit in them are queued in later turns
+fn push_front_fair(queue: &mut VecDeque<Song>, songs: Vec<Song>) {
+    let mut requesters: Vec<Option<UserId>> = vec![];
+    for song in songs.iter() {
+        if !requesters.contains(&song.requester()) {
+            requesters.push(song.requester());
+        }
+    }
+    let mut songs = songs;
+    for requester in requesters {
+        let (new_songs, rest) = songs
+            .into_iter()
+            .partition(|song| song.requester() == requester);
+        songs = rest;
+        let turns: Vec<usize> = (0..queue.len())
+            .filter(|&index| queue[index].requester() == requester)
+            .collect();
+        // removed from the back, so the indices of the remaining turns stay valid
+        let mut existing: Vec<Song> = turns
+            .iter()
+            .rev()
+            .map(|&index| queue.remove(index).unwrap())
+            .collect();
+        existing.reverse();
+        let mut sub_queue = new_songs.into_iter().chain(existing);
+        for &index in turns.iter() {
+            queue.insert(index, sub_queue.next().unwrap());
+        }
+        for song in sub_queue {
+            insert_fair(queue, song);
+        }
+    }
 }
 
 impl SongQueue {
-    pub fn new() -> SongQueue {
+    pub fn new(
+        player_wakeup: Arc<Notify>,
+        fair: bool,
+        policy: QueuePolicy,
+        loudnorm_targets: LoudnormTargets,
+        cache: Arc<AudioCache>,
+        // receives the songs that fail to load. Those that can never load are dropped from the queue
+        failures: UnboundedSender<(SongRecord, LoadFailure)>,
+    ) -> SongQueue {
         let queue = Arc::new(Mutex::new(VecDeque::new()));
-        let loader = Arc::new(Mutex::new(SongLoader::start_new(queue.clone())));
-        SongQueue { loader, queue }
+        let loader_wakeup = Arc::new(Notify::new());
+        let policy = Arc::new(Mutex::new(policy));
+        let loudnorm_targets = Arc::new(Mutex::new(loudnorm_targets));
+        let loader = Arc::new(Mutex::new(SongLoader::start_new(
+            queue.clone(),
+            loader_wakeup.clone(),
+            player_wakeup.clone(),
+            policy.clone(),
+            loudnorm_targets.clone(),
+            cache,
+            failures,
+        )));
+        SongQueue {
+            loader,
+            queue,
+            loader_wakeup,
+            player_wakeup,
+            fair: AtomicBool::new(fair),
+            policy,
+            loudnorm_targets,
+        }
+    }
+    // must be called whenever songs are added or reordered
+    fn notify_changed(&self) {
+        self.loader_wakeup.notify_one();
+        self.player_wakeup.notify_one();
     }
     pub async fn push(
         &self,
-        mut songs: Vec<Song>,
+        songs: Vec<Song>,
         queue_position: QueuePosition,
     ) -> anyhow::Result<()> {
         let mut queue = self.queue.lock().await;
+        self.push_locked(&mut queue, songs, queue_position);
+        Ok(())
+    }
+    // pushes the songs that the queue policy allows, returns the songs that were not pushed with
+    // the reason why
+    pub async fn push_limited(
+        &self,
+        songs: Vec<Song>,
+        queue_position: QueuePosition,
+    ) -> Vec<(String, Rejection)> {
+        let policy = self.policy.lock().await.clone();
+        let mut queue = self.queue.lock().await;
+        let mut queue_len = queue.len();
+        let mut requester_songs: HashMap<Option<UserId>, usize> = HashMap::new();
+        for song in queue.iter() {
+            *requester_songs.entry(song.requester()).or_default() += 1;
+        }
+        let mut accepted = vec![];
+        let mut rejections = vec![];
+        for song in songs {
+            let count = requester_songs.entry(song.requester()).or_default();
+            match policy.check(&song, queue_len, *count) {
+                Some(rejection) => rejections.push((song.get_string().await, rejection)),
+                None => {
+                    queue_len += 1;
+                    *count += 1;
+                    accepted.push(song);
+                }
+            }
+        }
+        if !accepted.is_empty() {
+            self.push_locked(&mut queue, accepted, queue_position);
+        }
+        rejections
+    }
+    // applies to songs added or loaded from now on
+    pub async fn set_policy(&self, policy: QueuePolicy) {
+        *self.policy.lock().await = policy;
+    }
+    // songs that are already loaded keep their normalization
+    pub async fn set_loudnorm_targets(&self, targets: LoudnormTargets) {
+        *self.loudnorm_targets.lock().await = targets;
+    }
+    fn push_locked(
+        &self,
+        queue: &mut VecDeque<Song>,
+        mut songs: Vec<Song>,
+        queue_position: QueuePosition,
+    ) {
+        if self.fair.load(Ordering::Relaxed) {
+            // the position applies within the requester's own songs, and songs that were
+            // reordered by hand stay where they are
+            match queue_position {
+                QueuePosition::Back => songs.into_iter().for_each(|song| insert_fair(queue, song)),
+                QueuePosition::Front => push_front_fair(queue, songs),
+            }
+            self.notify_changed();
+            return;
+        }
         if let QueuePosition::Front = queue_position {
             songs.reverse();
         };
@@ -28,18 +230,16 @@ impl SongQueue {
             QueuePosition::Front => VecDeque::push_front,
         };
         for song in songs.into_iter() {
-            push(&mut queue, song);
+            push(queue, song);
         }
-        Ok(())
+        self.notify_changed();
     }
+    // pops the next song once the loader is done with it, which includes songs that failed
     pub async fn try_pop_ready_song(&self) -> Option<Song> {
         let mut queue = self.queue.lock().await;
-        let next_song = queue.front();
-        let audio_reader_config = next_song.and_then(Song::get_buf_config);
-        if let (Some(_), Some(_)) = (next_song, audio_reader_config) {
-            queue.pop_front()
-        } else {
-            None
+        match queue.front() {
+            Some(song) if song.is_loaded() => queue.pop_front(),
+            _ => None,
         }
     }
     pub async fn shuffle(&self) -> anyhow::Result<()> {
@@ -48,9 +248,134 @@ impl SongQueue {
             return Err(anyhow!("queue is empty"));
         }
         queue.make_contiguous().shuffle(&mut rand::thread_rng());
+        if self.fair.load(Ordering::Relaxed) {
+            *queue = interleave(queue.drain(..));
+        }
+        self.notify_changed();
 
         Ok(())
     }
+    pub async fn set_fair(&self, fair: bool) {
+        let mut queue = self.queue.lock().await;
+        self.fair.store(fair, Ordering::Relaxed);
+        if fair {
+            *queue = interleave(queue.drain(..));
+            self.notify_changed();
+        }
+    }
+    // positions are 1-indexed, matching the numbering shown by get_string
+    fn queue_index(queue: &VecDeque<Song>, position: usize) -> anyhow::Result<usize> {
+        match position {
+            0 => Err(anyhow!("queue positions start at 1")),
+            position if position > queue.len() => Err(anyhow!(
+                "position {position} is out of range, queue has {} songs",
+                queue.len()
+            )),
+            position => Ok(position - 1),
+        }
+    }
+    // if requester is given, only songs requested by them may be removed
+    fn check_requester(
+        queue: &VecDeque<Song>,
+        index: usize,
+        requester: Option<UserId>,
+    ) -> anyhow::Result<()> {
+        match requester {
+            Some(requester) if queue[index].requester() != Some(requester) => Err(anyhow!(
+                "song {} was not requested by you, you can only remove your own songs",
+                index + 1
+            )),
+            _ => Ok(()),
+        }
+    }
+    pub async fn remove(&self, position: usize, requester: Option<UserId>) -> anyhow::Result<Song> {
+        let mut queue = self.queue.lock().await;
+        let index = Self::queue_index(&queue, position)?;
+        Self::check_requester(&queue, index, requester)?;
+        let song = queue.remove(index).unwrap();
+        self.notify_changed();
+        Ok(song)
+    }
+    // removes every song from start to end inclusive, returns the number of songs removed
+    pub async fn remove_range(
+        &self,
+        start: usize,
+        end: usize,
+        requester: Option<UserId>,
+    ) -> anyhow::Result<usize> {
+        let mut queue = self.queue.lock().await;
+        let start_index = Self::queue_index(&queue, start)?;
+        let end_index = Self::queue_index(&queue, end)?;
+        if start_index > end_index {
+            return Err(anyhow!("invalid range {start}-{end}"));
+        }
+        for index in start_index..=end_index {
+            Self::check_requester(&queue, index, requester)?;
+        }
+        let removed = queue.drain(start_index..=end_index).count();
+        self.notify_changed();
+        Ok(removed)
+    }
+    // removes the songs with the given ids that are still queued, returns the number removed
+    pub async fn remove_ids(
+        &self,
+        ids: &[u64],
+        requester: Option<UserId>,
+    ) -> anyhow::Result<usize> {
+        let mut queue = self.queue.lock().await;
+        for (index, song) in queue.iter().enumerate() {
+            if ids.contains(&song.id()) {
+                Self::check_requester(&queue, index, requester)?;
+            }
+        }
+        let len = queue.len();
+        queue.retain(|song| !ids.contains(&song.id()));
+        let removed = len - queue.len();
+        if removed > 0 {
+            self.notify_changed();
+        }
+        Ok(removed)
+    }
+    pub async fn move_to(&self, from: usize, to: usize) -> anyhow::Result<()> {
+        let mut queue = self.queue.lock().await;
+        let from_index = Self::queue_index(&queue, from)?;
+        let to_index = Self::queue_index(&queue, to)?;
+        let song = queue.remove(from_index).unwrap();
+        queue.insert(to_index, song);
+        self.notify_changed();
+        Ok(())
+    }
+    pub async fn swap(&self, a: usize, b: usize) -> anyhow::Result<()> {
+        let mut queue = self.queue.lock().await;
+        let a_index = Self::queue_index(&queue, a)?;
+        let b_index = Self::queue_index(&queue, b)?;
+        queue.swap(a_index, b_index);
+        self.notify_changed();
+        Ok(())
+    }
+    // drops every song in front of the given position, so that it becomes the next song to play
+    pub async fn skip_to(&self, position: usize) -> anyhow::Result<()> {
+        let mut queue = self.queue.lock().await;
+        let index = Self::queue_index(&queue, position)?;
+        queue.drain(..index);
+        self.notify_changed();
+        Ok(())
+    }
+    // queues every song that failed to load for loading again, returns the number of such songs
+    pub async fn retry_failed(&self) -> usize {
+        let mut queue = self.queue.lock().await;
+        let mut retried = 0;
+        for song in queue.iter_mut() {
+            if song.load_failure().is_some_and(LoadFailure::is_retryable) {
+                *song = song.record().into_song();
+                retried += 1;
+            }
+        }
+        if retried > 0 {
+            self.notify_changed();
+        }
+        retried
+    }
     pub async fn clear(&self) -> anyhow::Result<()> {
         let mut queue = self.queue.lock().await;
         queue.clear();
@@ -61,22 +386,143 @@ impl SongQueue {
         loader.cleanup().await?;
         Ok(())
     }
-    pub async fn get_string(&self) -> String {
+    pub async fn get_records(&self) -> Vec<SongRecord> {
+        let queue = self.queue.lock().await;
+        queue.iter().map(Song::record).collect()
+    }
+    // the ids and descriptions of the first songs in the queue
+    pub async fn get_song_entries(&self, amount: usize) -> Vec<(u64, String)> {
+        let queue = self.queue.lock().await;
+        let mut res = vec![];
+        for song in queue.iter().take(amount) {
+            res.push((
+                song.id(),
+                format!("{} {}", song.status_marker(), song.get_string().await),
+            ));
+        }
+        res
+    }
+    // returns the given page of the queue (0-indexed, clamped to the last page), and the number
+    // of pages
+    pub async fn get_page_string(&self, page: usize, page_size: usize) -> (String, usize) {
         let queue = self.queue.lock().await;
         if queue.is_empty() {
-            return "*empty*".to_string();
+            return ("*empty*".to_string(), 1);
         };
-        let mut s = String::new();
-        s += &format!(
-            "*Showing {} of {} songs*\n",
-            min(20, queue.len()),
-            queue.len()
+        let num_pages = queue.len().div_ceil(page_size);
+        let page = min(page, num_pages - 1);
+        let total_duration: Duration = queue.iter().filter_map(Song::duration).sum();
+        let has_unknown_duration = queue.iter().any(|song| song.duration().is_none());
+        let mut s = format!(
+            "*{} songs | {}{}*\n*⏳ waiting | 🔄 loading | ✅ ready | ⚠️ failed*\n",
+            queue.len(),
+            format_duration(total_duration),
+            match has_unknown_duration {
+                true => "+",
+                false => "",
+            }
         );
-        for (i, song) in queue.iter().take(20).enumerate() {
-            s += &format!("{}. ", i + 1);
+        for (i, song) in queue
+            .iter()
+            .enumerate()
+            .skip(page * page_size)
+            .take(page_size)
+        {
+            s += &format!("{}. {} ", i + 1, song.status_marker());
             s += &song.get_string().await;
+            if let Some(requester) = song.requester() {
+                s += &format!(" | <@{requester}>");
+            }
+            if let Some(reason) = song.load_failure() {
+                s += &format!(" | failed: {reason}");
+            }
             s += "\n";
         }
-        s
+        (s, num_pages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{
+        song::{HowToFind, SongMetadata},
+        types::StreamType,
+    };
+
+    // the requester is the first letter of the title, e.g. "a1" is requested by user 1
+    fn song(title: &str) -> Song {
+        let requester = (title.as_bytes()[0] - b'a') as u64 + 1;
+        let metadata = SongMetadata {
+            artist: None,
+            title: Some(title.to_string()),
+            how_to_find: HowToFind::SearchQuery(title.to_string()),
+            duration: None,
+            requester: Some(UserId::new(requester)),
+            is_live: false,
+        };
+        Song::new_load(metadata, StreamType::Online)
+    }
+
+    fn songs(titles: &str) -> Vec<Song> {
+        titles.split(' ').map(song).collect()
+    }
+
+    fn titles(queue: &VecDeque<Song>) -> String {
+        queue
+            .iter()
+            .map(|song| {
+                let string = song.record().get_string();
+                string.split(' ').next().unwrap().to_string()
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn interleaves_requesters() {
+        let queue = interleave(songs("a1 a2 a3 b1 b2 c1"));
+        assert_eq!(titles(&queue), "a1 b1 c1 a2 b2 a3");
+    }
+
+    #[test]
+    fn enabling_fair_mode_interleaves_the_queue() {
+        // what set_fair does to a queue that was filled while fair mode was off
+        let mut queue = VecDeque::from(songs("a1 a2 b1 a3 b2"));
+        queue = interleave(queue.drain(..));
+        assert_eq!(titles(&queue), "a1 b1 a2 b2 a3");
+    }
+
+    #[test]
+    fn inserts_new_requester_in_the_first_round() {
+        let mut queue = interleave(songs("a1 a2 a3 b1 b2"));
+        insert_fair(&mut queue, song("c1"));
+        assert_eq!(titles(&queue), "a1 b1 c1 a2 b2 a3");
+        insert_fair(&mut queue, song("c2"));
+        assert_eq!(titles(&queue), "a1 b1 c1 a2 b2 c2 a3");
+        insert_fair(&mut queue, song("b3"));
+        assert_eq!(titles(&queue), "a1 b1 c1 a2 b2 c2 a3 b3");
+    }
+
+    #[test]
+    fn insert_keeps_songs_moved_by_hand() {
+        // b1 was moved to the front by hand, and stays there
+        let mut queue = VecDeque::from(songs("b1 a1 a2"));
+        insert_fair(&mut queue, song("b2"));
+        assert_eq!(titles(&queue), "b1 a1 a2 b2");
+    }
+
+    #[test]
+    fn pushes_to_the_front_of_the_requesters_turns() {
+        let mut queue = interleave(songs("a1 a2 b1 b2"));
+        push_front_fair(&mut queue, songs("a0"));
+        assert_eq!(titles(&queue), "a0 b1 a1 b2 a2");
+    }
+
+    #[test]
+    fn pushes_new_requester_to_the_front_in_turn() {
+        let mut queue = interleave(songs("a1 a2 b1 b2"));
+        push_front_fair(&mut queue, songs("c1 c2"));
+        assert_eq!(titles(&queue), "a1 b1 c1 a2 b2 c2");
     }
 }
@@ -1,11 +1,14 @@
 use rspotify::model as rspotify;
-use std::sync::Arc;
+use std::{ffi::OsStr, io::Cursor, path::Path, sync::Arc};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
+use poise::serenity_prelude::{Attachment, UserId};
 
 use super::{
+    config, icy,
     song::{HowToFind, Song, SongMetadata},
     spotify::SpotifyClient,
+    tags::{read_tags, AudioTags},
     types::StreamType,
     ytdl,
 };
@@ -17,6 +20,8 @@ enum Query<'a> {
     SpotifyTrack(rspotify::TrackId<'a>),
     YoutubeTrack(HowToFind),
     YoutubePlaylist { url: String },
+    // any other url, e.g. an audio file or internet radio stream
+    DirectUrl { url: String },
 }
 
 fn parse_query<'a>(query: &'a str) -> anyhow::Result<Query<'a>> {
@@ -70,18 +75,30 @@ fn parse_query<'a>(query: &'a str) -> anyhow::Result<Query<'a>> {
             HowToFind::SearchQuery(query.to_string())
         };
         Ok(Query::YoutubeTrack(how_to_find))
+    } else if query.starts_with("http://") || query.starts_with("https://") {
+        Ok(Query::DirectUrl {
+            url: query.to_string(),
+        })
     } else {
         Err(anyhow!("unrecongized query {query}"))
     }
 }
 
-pub async fn process_query(query: &str, stream_type: StreamType) -> anyhow::Result<Vec<Song>> {
+pub async fn process_query(
+    query: &str,
+    stream_type: StreamType,
+    requester: UserId,
+) -> anyhow::Result<Vec<Song>> {
     let query = parse_query(query)?;
     match query {
         Query::SpotifyPlaylist(playlist_id) => {
             let client = SpotifyClient::new().await?;
             let tracks = client.get_playlist(playlist_id).await?;
-            Ok(SpotifyClient::process_track_objects(tracks, stream_type))
+            Ok(SpotifyClient::process_track_objects(
+                tracks,
+                stream_type,
+                requester,
+            ))
         }
         Query::SpotifyTrack(track_id) => {
             let client = SpotifyClient::new().await?;
@@ -89,12 +106,17 @@ pub async fn process_query(query: &str, stream_type: StreamType) -> anyhow::Resu
             Ok(SpotifyClient::process_track_objects(
                 vec![track],
                 stream_type,
+                requester,
             ))
         }
         Query::SpotifyAlbum(album_id) => {
             let client = SpotifyClient::new().await?;
             let tracks = client.get_album(album_id).await?;
-            Ok(SpotifyClient::process_track_objects(tracks, stream_type))
+            Ok(SpotifyClient::process_track_objects(
+                tracks,
+                stream_type,
+                requester,
+            ))
         }
         Query::YoutubeTrack(how_to_find) => {
             let metadata = SongMetadata {
@@ -102,18 +124,119 @@ pub async fn process_query(query: &str, stream_type: StreamType) -> anyhow::Resu
                 title: None,
                 duration: None,
                 how_to_find,
+                requester: Some(requester),
+                is_live: false,
             };
             let song = Song::new_load(metadata, stream_type);
             Ok(vec![song])
         }
-        Query::YoutubePlaylist { url } => ytdl::ytdl_process_playlist(&url, stream_type).await,
+        Query::YoutubePlaylist { url } => {
+            ytdl::ytdl_process_playlist(&url, stream_type, requester).await
+        }
+        Query::DirectUrl { url } => {
+            let info = icy::probe_url(&url).await?;
+            // radio stations are named after the station, files after the last part of the path
+            let title = info.name.unwrap_or_else(|| {
+                url.split(['?', '#'])
+                    .next()
+                    .and_then(|path| path.rsplit('/').next())
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or(&url)
+                    .to_string()
+            });
+            let metadata = SongMetadata {
+                artist: None,
+                title: Some(title),
+                duration: None,
+                how_to_find: HowToFind::DirectUrl(url),
+                requester: Some(requester),
+                is_live: info.is_live,
+            };
+            Ok(vec![Song::new_load(metadata, stream_type)])
+        }
     }
 }
 
+// the first bytes of an attachment, which is all of it if it is smaller than max_len
+async fn download_prefix(url: &str, max_len: usize) -> anyhow::Result<Vec<u8>> {
+    let mut response = reqwest::Client::builder()
+        .timeout(config::audio::URL_REQUEST_TIMEOUT)
+        .build()
+        .context("failed to build http client")?
+        .get(url)
+        .header("Range", format!("bytes=0-{}", max_len - 1))
+        .send()
+        .await?
+        .error_for_status()?;
+    // servers may ignore the range and send the whole file, so stop reading once there is enough
+    let mut buf = vec![];
+    while buf.len() < max_len {
+        match response.chunk().await? {
+            Some(chunk) => buf.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    buf.truncate(max_len);
+    Ok(buf)
+}
+
+// reads the metadata of an uploaded audio file from its first bytes. The file is downloaded in
+// full when loading, since only its url is kept
+pub async fn process_attachment(
+    attachment: &Attachment,
+    stream_type: StreamType,
+    requester: UserId,
+) -> anyhow::Result<Song> {
+    let filename = &attachment.filename;
+    if attachment.size > config::audio::MAX_ATTACHMENT_SIZE {
+        return Err(anyhow!(
+            "{filename} is larger than the max of {} MB",
+            config::audio::MAX_ATTACHMENT_SIZE / (1024 * 1024)
+        ));
+    }
+    let buf = download_prefix(&attachment.url, config::audio::ATTACHMENT_PROBE_SIZE)
+        .await
+        .with_context(|| format!("failed to download {filename}"))?;
+    let is_complete = buf.len() >= attachment.size as usize;
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_string);
+    let tags = tokio::task::spawn_blocking(move || {
+        read_tags(Box::new(Cursor::new(buf)), extension.as_deref())
+    })
+    .await?;
+    let tags = match tags {
+        Ok(tags) if is_complete => tags,
+        // durations estimated from part of a file are wrong, the loader finds the real one
+        Ok(tags) => AudioTags {
+            duration: None,
+            ..tags
+        },
+        Err(why) if is_complete => return Err(why.context(format!("failed to read {filename}"))),
+        // some formats keep their metadata at the end of the file. If it isn't audio after all,
+        // the loader will fail to load it
+        Err(why) => {
+            log::warn!("failed to read the tags of {}: {}", filename, why);
+            AudioTags::default()
+        }
+    };
+    let metadata = SongMetadata {
+        artist: tags.artist,
+        title: tags.title.or_else(|| Some(filename.clone())),
+        how_to_find: HowToFind::Attachment(attachment.url.clone()),
+        duration: tags.duration,
+        requester: Some(requester),
+        is_live: false,
+    };
+    Ok(Song::new_load(metadata, stream_type))
+}
+
 pub async fn song_recommender(
     query: &str,
     amount: usize,
     stream_type: StreamType,
+    requester: UserId,
 ) -> anyhow::Result<Vec<Song>> {
     let split: Vec<&str> = query
         .split("/playlist/")
@@ -131,5 +254,9 @@ pub async fn song_recommender(
     let client = Arc::new(SpotifyClient::new().await?);
     let playlist_id = rspotify::PlaylistId::from_id(playlist_id)?;
     let tracks = client.recommend_playlist(amount, playlist_id).await?;
-    Ok(SpotifyClient::process_track_objects(tracks, stream_type))
+    Ok(SpotifyClient::process_track_objects(
+        tracks,
+        stream_type,
+        requester,
+    ))
 }
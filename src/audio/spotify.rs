@@ -1,4 +1,5 @@
 use anyhow::Context;
+use poise::serenity_prelude::UserId;
 use rspotify::{
     clients::BaseClient,
     model::{
@@ -51,13 +52,13 @@ impl TrackObject {
             TrackObject::SimplifiedTrack(track) => track.duration.num_seconds(),
         }
     }
-    fn album_id(&self) -> Option<&AlbumId> {
+    fn album_id(&self) -> Option<&AlbumId<'_>> {
         match self {
             TrackObject::FullTrack(track) => track.album.id.as_ref(),
             TrackObject::SimplifiedTrack(_) => None,
         }
     }
-    fn artist_id(&self) -> Option<&ArtistId> {
+    fn artist_id(&self) -> Option<&ArtistId<'_>> {
         match self {
             TrackObject::FullTrack(track) => track.artists[0].id.as_ref(),
             TrackObject::SimplifiedTrack(track) => track.artists[0].id.as_ref(),
@@ -85,7 +86,11 @@ impl SpotifyClient {
         Ok(SpotifyClient { client: spotify })
     }
 
-    pub fn process_track_objects(tracks: Vec<TrackObject>, stream_type: StreamType) -> Vec<Song> {
+    pub fn process_track_objects(
+        tracks: Vec<TrackObject>,
+        stream_type: StreamType,
+        requester: UserId,
+    ) -> Vec<Song> {
         tracks
             .iter()
             .map(|track| {
@@ -98,6 +103,8 @@ impl SpotifyClient {
                     title: Some(title.to_string()),
                     duration: Some(track.duration() as u64),
                     how_to_find,
+                    requester: Some(requester),
+                    is_live: false,
                 };
 
                 Song::new_load(metadata, stream_type)
@@ -0,0 +1,61 @@
+use anyhow::Context;
+use symphonia::core::{
+    formats::FormatOptions,
+    io::{MediaSource, MediaSourceStream},
+    meta::{MetadataOptions, StandardTagKey, Tag},
+    probe::Hint,
+};
+
+// the metadata read from an audio file
+#[derive(Default)]
+pub struct AudioTags {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<u64>,
+}
+
+// the extension is a hint for the file format. Fails if the source is not a supported audio file.
+// Reads from the source, so should not be called from async code
+pub fn read_tags(
+    source: Box<dyn MediaSource>,
+    extension: Option<&str>,
+) -> anyhow::Result<AudioTags> {
+    let stream = MediaSourceStream::new(source, Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let mut probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .context("not a supported audio file")?;
+
+    // tags may be read while probing (e.g. ID3v2 for mp3) or be part of the container
+    let mut tags: Vec<Tag> = vec![];
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        tags.extend_from_slice(revision.tags());
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.extend_from_slice(revision.tags());
+    }
+    let tag = |key: StandardTagKey| {
+        tags.iter()
+            .find(|tag| tag.std_key == Some(key))
+            .map(|tag| tag.value.to_string())
+    };
+    let duration = probed.format.default_track().and_then(|track| {
+        let params = &track.codec_params;
+        Some(params.time_base?.calc_time(params.n_frames?).seconds)
+    });
+    Ok(AudioTags {
+        artist: tag(StandardTagKey::Artist),
+        title: tag(StandardTagKey::TrackTitle),
+        album: tag(StandardTagKey::Album),
+        duration,
+    })
+}
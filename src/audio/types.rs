@@ -1,36 +1,153 @@
-#[derive(Copy, Clone)]
+use std::{fmt, path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::util::format_duration;
+
+#[derive(Copy, Clone, Default)]
 pub enum QueuePosition {
+    #[default]
     Front,
     Back,
 }
 
-impl Default for QueuePosition {
-    fn default() -> Self {
-        Self::Front
-    }
-}
-
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub enum StreamType {
     Online,
+    // two-pass EBU R128 normalization to the guild's loudnorm targets
+    #[default]
     Loudnorm,
+    // evens out quiet and loud parts by adapting the volume over time
+    Dynaudnorm,
+    // a constant gain that brings the peak up or down to the guild's true peak target
+    PeakNorm,
+}
+
+// what loudnorm normalizes audio to. PeakNorm uses the true peak target too
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoudnormTargets {
+    // in LUFS
+    pub integrated: f64,
+    // loudness range, in LU
+    pub lra: f64,
+    // in dBTP
+    pub true_peak: f64,
 }
 
-impl Default for StreamType {
+impl Default for LoudnormTargets {
     fn default() -> Self {
-        Self::Loudnorm
+        Self {
+            integrated: -16.0,
+            lra: 11.0,
+            true_peak: -1.5,
+        }
+    }
+}
+
+impl LoudnormTargets {
+    pub fn get_string(&self) -> String {
+        format!(
+            "**Integrated loudness:** {:.1} LUFS\n**Loudness range:** {:.1} LU\n**True peak:** {:.1} dBTP",
+            self.integrated, self.lra, self.true_peak
+        )
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LoopMode {
+    #[default]
+    Off,
+    Track,
+    Queue,
+}
+
+impl LoopMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Track,
+            Self::Track => Self::Queue,
+            Self::Queue => Self::Off,
+        }
+    }
+}
+
+impl fmt::Display for LoopMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Off => "off",
+            Self::Track => "track",
+            Self::Queue => "queue",
+        };
+        f.write_str(name)
+    }
+}
+
+pub enum SkipOutcome {
+    Skipped,
+    Voted { votes: usize, required: usize },
+}
+
+// why a song could not be loaded
+#[derive(Clone)]
+pub enum LoadFailure {
+    YtDlp(String),
+    Timeout,
+    Ffmpeg(String),
+    // a direct url couldn't be downloaded
+    Download(String),
+    // rejected by the queue policy once the duration was known
+    TooLong { duration: Duration, max: Duration },
+}
+
+impl LoadFailure {
+    // whether loading the song again might succeed
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, Self::TooLong { .. })
+    }
+}
+
+impl fmt::Display for LoadFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::YtDlp(why) => write!(f, "yt-dlp error: {why}"),
+            Self::Timeout => write!(f, "timed out"),
+            Self::Ffmpeg(why) => write!(f, "ffmpeg error: {why}"),
+            Self::Download(why) => write!(f, "download error: {why}"),
+            Self::TooLong { duration, max } => write!(
+                f,
+                "{} is longer than the max of {}",
+                format_duration(*duration),
+                format_duration(*max)
+            ),
+        }
     }
 }
 
 #[derive(Clone)]
 pub enum AudioReaderConfig {
     Online { src_url: String },
+    // a url given by a user, streamed like an online source. It is requested by the bot rather
+    // than ffmpeg, so that it can't point into the bot's network
+    DirectUrl { url: String },
+    // streamed from disk, like an online source
+    LocalFile { path: PathBuf },
+    // audio normalized by any of the normalizing stream types
     Loudnorm { buf: Vec<u8> },
-    Error,
 }
 
-#[derive(Clone, PartialEq, Eq)]
+// where the loader gets a song's audio from
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum AudioSource {
+    // a url or search query for yt-dlp
+    Ytdl(String),
+    // a direct link to an audio file, read by ffmpeg
+    Url(String),
+    LocalFile(PathBuf),
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct SongLoaderWork {
-    pub query: String,
+    pub source: AudioSource,
     pub stream_type: StreamType,
 }
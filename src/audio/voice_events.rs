@@ -0,0 +1,66 @@
+use std::{sync::Arc, time::Duration};
+
+use poise::serenity_prelude::GuildId;
+use tokio::time::sleep;
+
+use crate::{util::format_duration, Data};
+
+// pauses playback when the bot is left alone in its voice channel, resumes it when someone
+// rejoins, and tears down the session if nobody rejoins within the guild's idle timeout
+pub async fn handle_voice_state_update(data: &Data, guild_id: GuildId) -> anyhow::Result<()> {
+    let audio_state = {
+        let audio_states = data.audio_states.lock().await;
+        match audio_states.get(&guild_id) {
+            Some(audio_state) => audio_state.clone(),
+            None => return Ok(()),
+        }
+    };
+    if audio_state.get_voice_channel().await.is_none() {
+        return Ok(());
+    }
+    let listeners = audio_state.get_listeners().await?;
+    if !listeners.is_empty() {
+        audio_state.set_idle_disconnect_handle(None).await;
+        return audio_state.auto_resume().await;
+    }
+
+    audio_state.auto_pause().await?;
+    if audio_state.has_idle_disconnect_handle().await {
+        return Ok(());
+    }
+    let idle_timeout = Duration::from_secs(audio_state.get_settings().await.idle_timeout_secs);
+    let job_handle = tokio::spawn({
+        let audio_states = data.audio_states.clone();
+        let audio_state = audio_state.clone();
+        async move {
+            sleep(idle_timeout).await;
+            audio_state.detach_idle_disconnect_handle().await;
+            {
+                let mut audio_states = audio_states.lock().await;
+                // the session may have been replaced, e.g. by an exit and restart
+                match audio_states.get(&guild_id) {
+                    Some(current) if Arc::ptr_eq(current, &audio_state) => {
+                        audio_states.remove(&guild_id);
+                    }
+                    _ => return,
+                }
+            }
+            if let Err(why) = audio_state.cleanup().await {
+                log::error!("error in handle_voice_state_update: {}", why);
+            }
+            if let Err(why) = audio_state
+                .announce(&format!(
+                    "Disconnected after {} without listeners",
+                    format_duration(idle_timeout)
+                ))
+                .await
+            {
+                log::error!("error in handle_voice_state_update: {}", why);
+            }
+        }
+    });
+    audio_state
+        .set_idle_disconnect_handle(Some(job_handle))
+        .await;
+    Ok(())
+}
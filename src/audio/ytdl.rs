@@ -1,4 +1,7 @@
-use anyhow::Context;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use poise::serenity_prelude::UserId;
 use serde::{Deserialize, Serialize};
 use tokio::process::Command as TokioCommand;
 
@@ -7,19 +10,59 @@ use super::{
     types::StreamType,
 };
 
-pub async fn ytdl_get_source_url(query: &str) -> String {
+pub struct YtdlSource {
+    pub src_url: String,
+    pub duration: Option<Duration>,
+    // identifies the track regardless of the query that found it
+    pub track_id: String,
+}
+
+pub async fn ytdl_get_source(query: &str) -> anyhow::Result<YtdlSource> {
     let mut cmd = TokioCommand::new("yt-dlp");
     let cmd = cmd
         .arg("-x")
         .arg("--skip-download")
-        .arg("--get-url")
+        // printed in this order, one per line
+        .arg("--print")
+        .arg("extractor_key")
+        .arg("--print")
+        .arg("id")
+        .arg("--print")
+        .arg("duration")
+        .arg("--print")
+        .arg("urls")
         //.arg("--audio-quality").arg("128k")
-        .arg(query);
-    let out = cmd.output().await.unwrap();
-
-    // let error = String::from_utf8(out.stderr).unwrap();
-    // println!("youtube-dl returned {}, err {}", &result, &error);
-    String::from_utf8(out.stdout).unwrap()
+        .arg(query)
+        // killed when the load is aborted, e.g. because the song was removed
+        .kill_on_drop(true);
+    let out = cmd.output().await.context("failed to run yt-dlp")?;
+    let stdout = String::from_utf8(out.stdout).context("yt-dlp output is not valid utf8")?;
+    // the duration is "NA" if unknown
+    let mut lines = stdout.splitn(4, '\n');
+    let (extractor, id, duration, src_url) = (
+        lines.next().unwrap_or_default(),
+        lines.next().unwrap_or_default(),
+        lines.next().unwrap_or("NA"),
+        lines.next().unwrap_or_default(),
+    );
+    if !out.status.success() || src_url.trim().is_empty() {
+        // the last line of stderr is usually the actual error
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let why = stderr
+            .lines()
+            .rfind(|line| !line.trim().is_empty())
+            .unwrap_or("no source url found");
+        return Err(anyhow!("{}", why.trim()));
+    }
+    Ok(YtdlSource {
+        src_url: src_url.to_string(),
+        duration: duration
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .map(Duration::from_secs_f64),
+        track_id: format!("{extractor}:{id}"),
+    })
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -35,13 +78,15 @@ struct PlaylistTrackInfo {
 pub async fn ytdl_process_playlist(
     playlist_url: &str,
     stream_type: StreamType,
+    requester: UserId,
 ) -> anyhow::Result<Vec<Song>> {
     let mut cmd = TokioCommand::new("yt-dlp");
     let cmd = cmd
         .arg("-x")
         .arg("--flat-playlist")
         .arg("-j")
-        .arg(playlist_url);
+        .arg(playlist_url)
+        .kill_on_drop(true);
     let out = cmd.output().await.unwrap();
     let stdout = String::from_utf8(out.stdout)?;
     let songs = stdout
@@ -60,6 +105,8 @@ pub async fn ytdl_process_playlist(
                         title: Some(track_info.title),
                         how_to_find: song::HowToFind::YoutubeTrackUrl(track_info.url),
                         duration: track_info.duration.map(|duration| duration as u64),
+                        requester: Some(requester),
+                        is_live: false,
                     };
                     Some(Song::new_load(metadata, stream_type))
                 }
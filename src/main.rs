@@ -1,12 +1,16 @@
 use audio::{
+    audio_cache::AudioCache,
     audio_state::AudioState,
     config::{self, audio::BOT_PREFIX},
     db::Db,
+    library::Library,
+    session::{self, SessionDb},
+    settings::SettingsDb,
 };
 use serenity::all::ClientBuilder;
 use songbird::SerenityInit;
 use std::{collections::HashMap, env, sync::Arc};
-use tokio::sync::Mutex;
+use tokio::{signal, sync::Mutex};
 use util::send_embed;
 
 mod audio;
@@ -14,7 +18,7 @@ mod logger;
 mod util;
 
 use poise::{
-    serenity_prelude::{CacheHttp, GatewayIntents, GuildId},
+    serenity_prelude::{CacheHttp, FullEvent, GatewayIntents, GuildId},
     Context as RawPoiseContext,
 };
 
@@ -22,7 +26,7 @@ type Error = Box<dyn std::error::Error + Send + Sync>;
 type PoiseContext<'a> = RawPoiseContext<'a, Data, Error>;
 
 pub struct Data {
-    pub audio_states: Mutex<HashMap<GuildId, Arc<AudioState>>>,
+    pub audio_states: Arc<Mutex<HashMap<GuildId, Arc<AudioState>>>>,
 }
 
 async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
@@ -38,6 +42,21 @@ async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
                 log::error!("Error while sending error embed: {}", e)
             };
         }
+        poise::FrameworkError::CommandCheckFailed {
+            error: Some(error),
+            ctx,
+            ..
+        } => {
+            if let Err(e) = send_embed(
+                ctx.serenity_context().http(),
+                ctx.channel_id(),
+                &format!("Error: {error}"),
+            )
+            .await
+            {
+                log::error!("Error while sending error embed: {}", e)
+            };
+        }
         error => {
             if let Err(e) = poise::builtins::on_error(error).await {
                 log::error!("Error while handling error: {}", e)
@@ -46,6 +65,30 @@ async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
     }
 }
 
+async fn event_handler(event: &FullEvent, data: &Data) -> Result<(), Error> {
+    if let FullEvent::VoiceStateUpdate { new, .. } = event {
+        if let Some(guild_id) = new.guild_id {
+            audio::handle_voice_state_update(data, guild_id).await?;
+        }
+    }
+    Ok(())
+}
+
+// resolves once the process is asked to stop, e.g. by ctrl+c or docker stop
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM");
+        tokio::select! {
+            _ = signal::ctrl_c() => (),
+            _ = sigterm.recv() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = signal::ctrl_c().await;
+}
+
 pub fn get_default_guilds() -> Vec<GuildId> {
     if let Ok(guilds) = env::var("OCTAVE_BOT_GUILDS") {
         return guilds
@@ -61,9 +104,12 @@ async fn main() {
     logger::init_logger().expect("failed to init logger");
     let mut commands = vec![];
     audio::add_group(&mut commands);
+    let audio_states = Arc::new(Mutex::new(HashMap::new()));
     let options = poise::FrameworkOptions {
         commands,
         on_error: |error| Box::pin(on_error(error)),
+        command_check: Some(|ctx| Box::pin(audio::check_command_permission(ctx))),
+        event_handler: |_ctx, event, _framework, data| Box::pin(event_handler(event, data)),
         prefix_options: poise::PrefixFrameworkOptions {
             prefix: Some(BOT_PREFIX.to_owned()),
             mention_as_prefix: false,
@@ -74,13 +120,18 @@ async fn main() {
     };
     let framework = poise::Framework::builder()
         .options(options)
-        .setup(|ctx, _, framework| {
-            Box::pin(async move {
-                poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                Ok(Data {
-                    audio_states: Mutex::new(HashMap::new()),
+        .setup({
+            let audio_states = audio_states.clone();
+            |ctx, _, framework| {
+                Box::pin(async move {
+                    poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+                    tokio::spawn(session::offer_restore(
+                        Arc::new(ctx.clone()),
+                        audio_states.clone(),
+                    ));
+                    Ok(Data { audio_states })
                 })
-            })
+            }
         })
         .build();
     let token = env::var(audio::config::env::DISCORD_BOT_TOKEN).expect("Error: token not found");
@@ -91,11 +142,40 @@ async fn main() {
     let client = ClientBuilder::new(token, intents)
         .framework(framework)
         .type_map_insert::<Db>(Db::new("./.db.json".to_string()).unwrap())
+        .type_map_insert::<SettingsDb>(SettingsDb::new("./.settings.json".to_string()).unwrap())
+        .type_map_insert::<SessionDb>(SessionDb::new("./.sessions.json".to_string()).unwrap())
+        .type_map_insert::<AudioCache>(Arc::new(
+            AudioCache::open("./.audio_cache".into(), config::audio::AUDIO_CACHE_MAX_SIZE).unwrap(),
+        ))
+        .type_map_insert::<Library>(
+            Library::index(
+                env::var(config::env::MUSIC_LIBRARY_DIR)
+                    .ok()
+                    .map(Into::into),
+            )
+            .unwrap(),
+        )
         .register_songbird()
         .await;
     rustls::crypto::ring::default_provider()
         .install_default()
         .expect("Failed to install rustls crypto provider");
 
-    client.unwrap().start().await.unwrap()
+    let mut client = client.unwrap();
+    tokio::spawn(session::run_session_saver(
+        audio_states.clone(),
+        client.data.clone(),
+    ));
+    tokio::spawn({
+        let type_map = client.data.clone();
+        let shard_manager = client.shard_manager.clone();
+        async move {
+            shutdown_signal().await;
+            if let Err(why) = session::save_sessions(&audio_states, &type_map).await {
+                log::error!("Error while saving sessions on shutdown: {}", why);
+            }
+            shard_manager.shutdown_all().await;
+        }
+    });
+    client.start().await.unwrap()
 }
@@ -1,6 +1,32 @@
+use std::time::Duration;
+
 use poise::serenity_prelude::{ChannelId, CreateEmbed, Http};
 use serenity::all::CreateMessage;
 
+// formats as m:ss, or h:mm:ss for durations of an hour or longer
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, mins, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match hours {
+        0 => format!("{}:{:0>2}", mins, secs),
+        hours => format!("{}:{:0>2}:{:0>2}", hours, mins, secs),
+    }
+}
+
+pub fn progress_bar(elapsed: Duration, total: Duration, width: usize) -> String {
+    let ratio = match total.is_zero() {
+        true => 0.0,
+        false => (elapsed.as_secs_f64() / total.as_secs_f64()).min(1.0),
+    };
+    let position = ((width - 1) as f64 * ratio).round() as usize;
+    (0..width)
+        .map(|i| match i == position {
+            true => '🔘',
+            false => '▬',
+        })
+        .collect()
+}
+
 pub fn get_styled_embed(text: &str) -> CreateEmbed {
     CreateEmbed::new().colour(0xf542bf).description(text)
 }